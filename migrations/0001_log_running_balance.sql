@@ -0,0 +1,5 @@
+-- balance of both sides right after the log was posted
+-- left null for failed transfers, unposted imports and rows from before this migration
+alter table plutus.log
+    add column origin_balance float8,
+    add column destination_balance float8;
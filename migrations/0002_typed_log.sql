@@ -0,0 +1,55 @@
+-- plutus.log used to keep origin, destination and state as serialized json in text columns
+-- sources are split into typed columns, the outcome gets a code that can be filtered/indexed on
+
+alter table plutus.log
+    add column origin_kind text,
+    add column origin_account bigint,
+    add column origin_description text,
+    add column destination_kind text,
+    add column destination_account bigint,
+    add column destination_description text,
+    add column outcome text;
+
+-- sources were either a bare variant ("Bank") or an object with one key ({"User": 123})
+update plutus.log set
+    origin_kind = case jsonb_typeof(origin::jsonb)
+        when 'string' then origin::jsonb #>> '{}'
+        else (select jsonb_object_keys(origin::jsonb) limit 1)
+    end,
+    destination_kind = case jsonb_typeof(destination::jsonb)
+        when 'string' then destination::jsonb #>> '{}'
+        else (select jsonb_object_keys(destination::jsonb) limit 1)
+    end,
+    -- "Success" or "<category>::<variant>", eg: "Account::InsufficientBalance"
+    outcome = case jsonb_typeof(state::jsonb)
+        when 'string' then state::jsonb #>> '{}'
+        else (
+            select k || '::' || case jsonb_typeof(state::jsonb -> k)
+                when 'string' then state::jsonb ->> k
+                else (select jsonb_object_keys(state::jsonb -> k) limit 1)
+            end
+            from jsonb_object_keys(state::jsonb) k limit 1
+        )
+    end;
+
+update plutus.log set
+    origin_account = case when jsonb_typeof(origin::jsonb -> origin_kind) = 'number' then (origin::jsonb ->> origin_kind)::bigint end,
+    origin_description = case when origin_kind = 'External' then origin::jsonb ->> origin_kind end,
+    destination_account = case when jsonb_typeof(destination::jsonb -> destination_kind) = 'number' then (destination::jsonb ->> destination_kind)::bigint end,
+    destination_description = case when destination_kind = 'External' then destination::jsonb ->> destination_kind end;
+
+alter table plutus.log
+    drop column origin,
+    drop column destination,
+    alter column origin_kind set not null,
+    alter column destination_kind set not null,
+    alter column outcome set not null,
+    -- kept as the full outcome, in case it carries more than the code
+    alter column state type jsonb using state::jsonb,
+    add constraint log_origin_kind_check check (origin_kind in ('Bank', 'User', 'AutoTransfer', 'Import', 'ImportPosted', 'External')),
+    add constraint log_destination_kind_check check (destination_kind in ('Bank', 'User', 'AutoTransfer', 'Import', 'ImportPosted', 'External'));
+
+-- every query on the log is per account, newest first
+create index log_origin_account_idx on plutus.log (origin_account, timestamp desc, id desc);
+create index log_destination_account_idx on plutus.log (destination_account, timestamp desc, id desc);
+create index log_outcome_idx on plutus.log (outcome);
@@ -0,0 +1,10 @@
+-- taken once a day by the daily tasks, to look up past balances without replaying the whole log
+create table plutus.balance_snapshot (
+    account bigint not null,
+    day bigint not null, -- epoch day the snapshot was taken on
+    timestamp float8 not null, -- epoch seconds, balance is as of this exact time
+    balance float8 not null,
+    primary key (account, day)
+);
+
+create index balance_snapshot_account_timestamp_idx on plutus.balance_snapshot (account, timestamp desc);
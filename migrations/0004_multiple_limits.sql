@@ -0,0 +1,4 @@
+-- accounts can have several limits at once now
+alter table plutus.limit drop constraint if exists limit_account_key;
+
+create index if not exists limit_account_idx on plutus.limit (account);
@@ -0,0 +1,4 @@
+-- rolling limits dont reset, their usage is whatever was sent out within the last window_hours
+alter table plutus.limit
+    add column rolling boolean not null default false,
+    add column window_hours integer not null default 0;
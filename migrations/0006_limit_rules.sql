@@ -0,0 +1,9 @@
+-- Transaction limits cap single transfers instead of adding up usage
+-- counterparty_* narrow a limit down to transfers going to that account/user
+-- exempt_own leaves out transfers between accounts of the same user
+alter table plutus.limit
+    add column species text not null default 'Cumulative',
+    add column counterparty_account bigint,
+    add column counterparty_user text,
+    add column exempt_own boolean not null default false,
+    add constraint limit_species_check check (species in ('Cumulative', 'Transaction'));
@@ -0,0 +1,10 @@
+-- Count limits cap how many transfers are made instead of how much is sent
+-- owner limits cover every account of that user, exactly one of account/owner is set
+alter table plutus.limit
+    alter column account drop not null,
+    add column owner text,
+    add constraint limit_scope_check check ((account is null) <> (owner is null)),
+    drop constraint if exists limit_species_check,
+    add constraint limit_species_check check (species in ('Cumulative', 'Transaction', 'Count'));
+
+create index if not exists limit_owner_idx on plutus.limit (owner);
@@ -0,0 +1,3 @@
+-- user wide limits can cap amounts as well as counts now
+-- existing (count) limits keep counting transfers between the user's own accounts, so exempt_own stays as it was
+alter table plutus.limit alter column exempt_own set default false;
@@ -0,0 +1,7 @@
+-- raising or deleting a limit is only applied once pending_effective (epoch day) is reached
+alter table plutus.limit
+    add column pending_cap float8,
+    add column pending_duration integer,
+    add column pending_window_hours integer,
+    add column pending_delete boolean not null default false,
+    add column pending_effective integer;
@@ -0,0 +1,16 @@
+-- thresholds are percentages of cap, alerted holds the ones already notified about this window
+alter table plutus.limit
+    add column thresholds float8[] not null default '{}',
+    add column alerted float8[] not null default '{}';
+
+create table if not exists plutus.notification (
+    id bigserial primary key,
+    owner text not null,
+    species text not null,
+    subject bigint not null,
+    message text not null,
+    timestamp float8 not null,
+    seen boolean not null default false
+);
+
+create index if not exists notification_owner_idx on plutus.notification (owner, id);
@@ -0,0 +1,14 @@
+-- schedules replace the fixed every x days duration, next_run (epoch day) is null once a schedule never runs again
+alter table plutus.auto_transfer
+    add column schedule jsonb,
+    add column next_run integer;
+
+update plutus.auto_transfer set
+    schedule = jsonb_build_object('Interval', duration),
+    next_run = last_transfer + duration;
+
+alter table plutus.auto_transfer
+    alter column schedule set not null,
+    drop column duration;
+
+create index if not exists auto_transfer_next_run_idx on plutus.auto_transfer (next_run);
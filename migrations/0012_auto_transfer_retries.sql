@@ -0,0 +1,11 @@
+-- failed runs are retried within their period, status is of the latest run
+alter table plutus.auto_transfer
+    add column retries integer not null default 2,
+    add column backoff_days integer not null default 1,
+    add column attempts integer not null default 0,
+    add column occurrence integer,
+    add column status text not null default 'Pending',
+    add column consecutive_failures integer not null default 0,
+    add column max_failures integer not null default 3,
+    add column paused boolean not null default false,
+    add constraint auto_transfer_status_check check (status in ('Pending', 'Succeeded', 'FailedRetrying', 'FailedFinal'));
@@ -0,0 +1,4 @@
+-- how amount is turned into what gets transferred, worked out when the transfer runs
+alter table plutus.auto_transfer
+    add column amount_species text not null default 'Fixed',
+    add constraint auto_transfer_amount_species_check check (amount_species in ('Fixed', 'Percentage', 'Above', 'TopUp'));
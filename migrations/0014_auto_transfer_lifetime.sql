@@ -0,0 +1,7 @@
+-- auto transfers can be limited to a range of days or a number of runs, completed ones are kept for history
+alter table plutus.auto_transfer
+    add column start_day integer,
+    add column end_day integer,
+    add column max_occurrences integer,
+    add column occurrences integer not null default 0,
+    add column completed boolean not null default false;
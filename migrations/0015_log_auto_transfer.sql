@@ -0,0 +1,4 @@
+-- which auto transfer a log came from, for run history
+alter table plutus.log add column auto_transfer bigint;
+
+create index if not exists log_auto_transfer_idx on plutus.log (auto_transfer, timestamp desc, id desc);
@@ -0,0 +1,7 @@
+-- what auto transfers do about runs missed while the daily tasks werent running
+alter table plutus.auto_transfer
+    add column catch_up text not null default 'Once',
+    add constraint auto_transfer_catch_up_check check (catch_up in ('Skip', 'Once', 'Every'));
+
+-- the missed day a late run made up for
+alter table plutus.log add column catch_up integer;
@@ -0,0 +1,2 @@
+-- auto transfers to a username, resolved to their default account every run
+alter table plutus.auto_transfer add column destination_user text;
@@ -0,0 +1,15 @@
+-- rounding outgoing transfers up into a savings account
+create table if not exists plutus.round_up (
+    id bigserial primary key,
+    account bigint not null unique,
+    savings bigint not null,
+    multiple float8 not null default 1,
+    batched boolean not null default false,
+    pending float8 not null default 0
+);
+
+alter table plutus.log
+    drop constraint log_origin_kind_check,
+    drop constraint log_destination_kind_check,
+    add constraint log_origin_kind_check check (origin_kind in ('Bank', 'User', 'AutoTransfer', 'RoundUp', 'Import', 'ImportPosted', 'External')),
+    add constraint log_destination_kind_check check (destination_kind in ('Bank', 'User', 'AutoTransfer', 'RoundUp', 'Import', 'ImportPosted', 'External'));
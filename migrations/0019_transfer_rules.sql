@@ -0,0 +1,19 @@
+-- rules passing on part of incoming transfers to other accounts
+create table if not exists plutus.transfer_rule (
+    id bigserial primary key,
+    account bigint not null,
+    destination bigint not null,
+    amount float8 not null,
+    amount_species text not null default 'Fixed' check (amount_species in ('Fixed', 'Percentage')),
+    sender bigint,
+    sender_user text,
+    min_amount float8
+);
+
+create index if not exists transfer_rule_account_idx on plutus.transfer_rule (account);
+
+alter table plutus.log
+    drop constraint log_origin_kind_check,
+    drop constraint log_destination_kind_check,
+    add constraint log_origin_kind_check check (origin_kind in ('Bank', 'User', 'AutoTransfer', 'RoundUp', 'Rule', 'Import', 'ImportPosted', 'External')),
+    add constraint log_destination_kind_check check (destination_kind in ('Bank', 'User', 'AutoTransfer', 'RoundUp', 'Rule', 'Import', 'ImportPosted', 'External'));
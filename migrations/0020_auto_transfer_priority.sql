@@ -0,0 +1,9 @@
+-- order of due auto transfers and sending what is available when the origin is short
+alter table plutus.auto_transfer
+    add column priority integer not null default 0,
+    add column partial boolean not null default false,
+    drop constraint auto_transfer_status_check,
+    add constraint auto_transfer_status_check check (status in ('Pending', 'Succeeded', 'Partial', 'FailedRetrying', 'FailedFinal'));
+
+-- how much a partial run fell short of what it should have sent
+alter table plutus.log add column shortfall float8;
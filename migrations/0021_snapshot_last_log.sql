@@ -0,0 +1,3 @@
+-- the latest log a snapshot's balance already includes, so replaying after it doesnt depend on timestamps
+-- older snapshots keep going by their timestamp
+alter table plutus.balance_snapshot add column last_log bigint;
@@ -0,0 +1,3 @@
+-- epoch day of the latest occurrence that is over with (run or skipped), rescheduling carries on from it
+-- unknown for older rows, those get rescheduled from today instead
+alter table plutus.auto_transfer add column last_occurrence integer;
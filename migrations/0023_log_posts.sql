@@ -0,0 +1,5 @@
+-- posting an import adds a log at the time it was posted, pointing back at the imported row
+-- rows posted before this were changed to ImportPosted in place and dont point anywhere
+alter table plutus.log add column posts bigint;
+
+create unique index if not exists log_posts_idx on plutus.log (posts) where posts is not null;
@@ -6,7 +6,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, Pool, Postgres, Row};
 
-use crate::{extractor_error::ExtractorError, limit::{Limit, LimitError}, log::{Log, LogSpecies, Source}, plutus_error::{Outcome, PlutusFormat}, session::RawSessionID, user::User, utils, AppState};
+use crate::{extractor_error::ExtractorError, limit::Limit, log::{Log, RunLink, Source}, plutus_error::{Outcome, PlutusFormat}, round_up::RoundUp, session::RawSessionID, transfer_rule::TransferRule, user::User, utils, AppState};
 
 const ID_LENGTH: u32 = 4 * 2;
 
@@ -104,7 +104,12 @@ impl Account {
     }
 
     // balance related
-    pub async fn transfer(db: &Pool<Postgres>, origin: i64, destination: i64, amount: f64, log: bool) -> Option<Outcome> {
+    pub async fn transfer(db: &Pool<Postgres>, origin: i64, destination: i64, amount: f64, source: fn(i64) -> Source) -> Option<Outcome> {
+        Account::transfer_linked(db, origin, destination, amount, source, None).await
+    }
+
+    // same as transfer, the log gets linked to the auto transfer run
+    pub async fn transfer_linked(db: &Pool<Postgres>, origin: i64, destination: i64, amount: f64, source: fn(i64) -> Source, run: Option<RunLink>) -> Option<Outcome> {
         // possible returns
         // AccountError::NoExist
         // AccountError::InsufficientBalance
@@ -126,35 +131,36 @@ impl Account {
             return Some(Outcome::Account(AccountError::InsufficientBalance));
         }
 
-        if Limit::check_limits(db, origin.id, amount).await {
-            return Some(Outcome::Limit(LimitError::WillSurpassLimit));
+        if let Some(e) = Limit::check_limits(db, &origin, &destination, amount).await {
+            return Some(Outcome::Limit(e));
         }
 
-        sqlx::query("update plutus.account set balance = balance - $1 where id = $2;")
+        let origin_balance = sqlx::query("update plutus.account set balance = balance - $1 where id = $2 returning balance;")
             .bind(amount)
             .bind(origin.id)
-            .execute(db)
-            .await.unwrap();
+            .fetch_one(db)
+            .await.unwrap()
+            .get::<f64, usize>(0);
 
-        sqlx::query("update plutus.account set balance = balance + $1 where id = $2;")
+        let destination_balance = sqlx::query("update plutus.account set balance = balance + $1 where id = $2 returning balance;")
             .bind(amount)
             .bind(destination.id)
-            .execute(db)
-            .await.unwrap();
-        
-        if log {
-            Log::append(db, amount, Source::User(origin.id), Source::User(destination.id), Outcome::Success).await;
+            .fetch_one(db)
+            .await.unwrap()
+            .get::<f64, usize>(0);
+
+        Log::append_with_balance(db, amount, source(origin.id), source(destination.id), (origin_balance, destination_balance), run).await;
+
+        Limit::increment_usage(db, &origin, &destination, amount).await;
+        Limit::check_thresholds(db, &origin, &destination).await;
+
+        // round ups dont get rounded up themselves
+        if !matches!(source(origin.id), Source::RoundUp(_)) {
+            RoundUp::on_outgoing(db, origin.id, destination.id, amount).await;
         }
 
-        match Limit::fetch(db, origin.id).await {
-            Some(l) => {
-                sqlx::query("update plutus.limit set usage = usage + $1 where id = $2;")
-                    .bind(amount)
-                    .bind(l.id)
-                    .execute(db)
-                    .await.unwrap();
-            },
-            None => {}
+        if TransferRule::triggered_by(&source(origin.id)) {
+            TransferRule::on_incoming(db, &origin, &destination, amount).await;
         }
 
         None
@@ -201,7 +207,7 @@ pub async fn user_transfer(
             return Outcome::Account(AccountError::InsufficientBalance);
         }
 
-        match Account::transfer(&db, origin.default_account, destination.default_account, amount, true).await {
+        match Account::transfer(&db, origin.default_account, destination.default_account, amount, Source::User).await {
             Some(o) => {
                 if o == Outcome::Account(AccountError::NoExist) {
                     return Outcome::Account(AccountError::NoPermission);
@@ -244,7 +250,7 @@ pub async fn account_to_user_transfer(
             return Outcome::Account(AccountError::InsufficientBalance);
         }
 
-        match Account::transfer(&db, origin, destination.default_account, amount, true).await {
+        match Account::transfer(&db, origin, destination.default_account, amount, Source::User).await {
             Some(o) => {
                 if o == Outcome::Account(AccountError::NoExist) {
                     return Outcome::Account(AccountError::NoPermission);
@@ -283,7 +289,7 @@ pub async fn account_transfer(
             return Outcome::Account(AccountError::InsufficientBalance);
         }
 
-        match Account::transfer(&db, origin, destination, amount, true).await {
+        match Account::transfer(&db, origin, destination, amount, Source::User).await {
             Some(o) => {
                 if o == Outcome::Account(AccountError::NoExist) {
                     return Outcome::Account(AccountError::NoPermission);
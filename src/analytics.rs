@@ -0,0 +1,194 @@
+// spending/income summaries over the log, so clients dont have to download the whole history
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use strum_macros::EnumString;
+
+use crate::{account::Account, extractor_error::ExtractorError, log::{self, Log, Source}, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+const TOP_COUNTERPARTIES: usize = 10;
+pub const MAX_RANGE: i64 = 86400 * 366 * 10; // 10 years
+
+#[derive(Serialize, Deserialize, EnumString, Clone, Copy)]
+pub enum Period {
+    Day,
+    Week, // starts on monday
+    Month
+}
+impl Period {
+    // epoch day that the period containing `day` starts on
+    pub fn start_of(&self, day: i64) -> i64 {
+        match self {
+            Period::Day => day,
+            // epoch day 0 was a thursday
+            Period::Week => (day + 3).div_euclid(7) * 7 - 3,
+            Period::Month => {
+                let (y, m, _) = utils::civil_from_epoch_day(day);
+                utils::epoch_day_from_civil(y, m, 1)
+            }
+        }
+    }
+
+    // epoch day that the period after the one starting on `start` starts on
+    pub fn next(&self, start: i64) -> i64 {
+        match self {
+            Period::Day => start + 1,
+            Period::Week => start + 7,
+            Period::Month => {
+                let (y, m, _) = utils::civil_from_epoch_day(start);
+                if m == 12 { utils::epoch_day_from_civil(y + 1, 1, 1) } else { utils::epoch_day_from_civil(y, m + 1, 1) }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PeriodSummary {
+    pub start: i64, // epoch seconds
+    pub spending: f64,
+    pub income: f64,
+    pub count: i64,
+
+    // relative to the period before, none if there was nothing to compare against
+    pub spending_change: Option<f64>,
+    pub income_change: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CounterpartySummary {
+    pub counterparty: Source,
+    pub spending: f64,
+    pub income: f64,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Analytics {
+    pub spending: f64,
+    pub income: f64,
+    pub count: i64,
+    pub average_transaction: f64,
+
+    pub periods: Vec<PeriodSummary>,
+    pub top_counterparties: Vec<CounterpartySummary>, // by total amount moved
+}
+
+fn change(previous: f64, current: f64) -> Option<f64> {
+    if previous == 0f64 {
+        None
+    } else {
+        Some((current - previous) / previous)
+    }
+}
+
+// transfers between the accounts given count as neither spending nor income
+pub async fn summarise(db: &Pool<Postgres>, accounts: &[i64], from: i64, to: i64, period: Period) -> Analytics {
+    let mut logs: Vec<Log> = vec![];
+    let mut seen = HashSet::new();
+    for a in accounts {
+        for l in Log::fetch_range(db, *a, from, to).await {
+            // transfers between two of the accounts show up twice
+            if seen.insert(l.id) {
+                logs.push(l);
+            }
+        }
+    }
+
+    let mut periods = vec![];
+    let mut start = period.start_of(from.div_euclid(86400));
+    while start * 86400 <= to {
+        periods.push(PeriodSummary { start: start * 86400, spending: 0f64, income: 0f64, count: 0, spending_change: None, income_change: None });
+        start = period.next(start);
+    }
+
+    let mut counterparties: HashMap<Source, CounterpartySummary> = HashMap::new();
+    for l in logs.iter().filter(|l| l.affects_balance()) {
+        let outgoing = l.origin.account().is_some_and(|a| accounts.contains(&a));
+        let incoming = l.destination.account().is_some_and(|a| accounts.contains(&a));
+        if outgoing == incoming {
+            continue;
+        }
+
+        let day = (l.timestamp as i64).div_euclid(86400);
+        // periods are in order, the last one starting at or before the log
+        let p = match periods.partition_point(|p| p.start <= day * 86400) {
+            0 => continue,
+            i => &mut periods[i - 1]
+        };
+        let other = if outgoing { &l.destination } else { &l.origin };
+        let c = counterparties.entry(other.clone())
+            .or_insert_with(|| CounterpartySummary { counterparty: other.clone(), spending: 0f64, income: 0f64, count: 0 });
+
+        if outgoing {
+            p.spending += l.balance;
+            c.spending += l.balance;
+        } else {
+            p.income += l.balance;
+            c.income += l.balance;
+        }
+        p.count += 1;
+        c.count += 1;
+    }
+
+    for i in 1..periods.len() {
+        periods[i].spending_change = change(periods[i - 1].spending, periods[i].spending);
+        periods[i].income_change = change(periods[i - 1].income, periods[i].income);
+    }
+
+    let mut counterparties = counterparties.into_values().collect::<Vec<CounterpartySummary>>();
+    counterparties.sort_by(|a, b| (b.spending + b.income).total_cmp(&(a.spending + a.income)));
+    counterparties.truncate(TOP_COUNTERPARTIES);
+
+    let spending = periods.iter().map(|p| p.spending).sum::<f64>();
+    let income = periods.iter().map(|p| p.income).sum::<f64>();
+    let count = periods.iter().map(|p| p.count).sum::<i64>();
+
+    Analytics {
+        spending,
+        income,
+        count,
+        average_transaction: if count == 0 { 0f64 } else { (spending + income) / count as f64 },
+        periods,
+        top_counterparties: counterparties
+    }
+}
+
+pub async fn fetch(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("from", PlutusFormat::BigNumber),
+        ("to", PlutusFormat::BigNumber),
+        ("period", PlutusFormat::Unspecified)
+    ], |db, session, query| async move {
+        // optional args
+        // "account" : only this account, otherwise all of the user's accounts
+        let accounts = match utils::optional_query::<i64>("account", &query) {
+            Ok(Some(_)) => match log::owned_account(&db, &query, session.user).await {
+                Ok(i) => vec![i],
+                Err(e) => return e
+            },
+            Ok(None) => Account::fetch_all(&db, session.user).await.into_iter().map(|a| a.id).collect(),
+            Err(e) => return Outcome::Plutus(e)
+        };
+
+        let period = match utils::from_query("period", &query).parse::<Period>() {
+            Ok(p) => p,
+            Err(_) => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        let from = utils::from_query("from", &query).parse::<i64>().unwrap();
+        let to = utils::from_query("to", &query).parse::<i64>().unwrap();
+        if to < from || to.saturating_sub(from) > MAX_RANGE {
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+
+        Outcome::Data(serde_json::to_string(&summarise(&db, &accounts, from, to, period).await).unwrap())
+    }).await
+}
@@ -3,69 +3,440 @@ use std::collections::HashMap;
 use axum::{extract::{Query, State}, response::IntoResponse, Json};
 use axum_extra::extract::WithRejection;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, Pool, Postgres};
+use sqlx::{prelude::FromRow, types::Json as SqlJson, Pool, Postgres};
+use strum_macros::{Display, EnumString};
 
-use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{Log, LogSpecies, Source}, plutus_error::{Outcome, PlutusFormat}, session::RawSessionID, utils, AppState};
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{Log, LogFilter, LogPage, RunLink, Source}, notification::{Notification, NotificationSpecies}, plutus_error::{Outcome, PlutusError, PlutusFormat}, schedule::Schedule, session::RawSessionID, user::User, utils, AppState};
+
+const DEFAULT_RETRIES: i32 = 2;
+const DEFAULT_BACKOFF_DAYS: i32 = 1;
+const DEFAULT_MAX_FAILURES: i32 = 3;
+const MAX_PREVIEW: usize = 100;
 
 #[derive(FromRow, Serialize, Deserialize)]
 pub struct AutoTransfer {
     pub id: i64,
     pub origin: i64,
-    pub destination: i64,
-    pub amount: f64,
-    pub duration: i32, // how often to transfer (every x number of days)
-    pub last_transfer: i32 // previous transfer (in epoch days)
+    pub destination: i64, // for destination_user, whichever account it resolved to last
+    pub destination_user: Option<String>, // sends to this user's default account at the time of each run
+    pub amount: f64, // meaning depends on amount_species
+    #[sqlx(try_from = "String")]
+    pub amount_species: AmountSpecies,
+    #[sqlx(json)]
+    pub schedule: Schedule, // how often to transfer
+    pub last_transfer: i32, // previous transfer (in epoch days)
+    pub next_run: Option<i32>, // epoch day, none if the schedule never runs again
+    pub last_occurrence: Option<i32>, // epoch day of the latest occurrence that is over with, run or skipped
+
+    // retrying within the period, the n-th retry happens backoff_days * 2^(n-1) days after the one before
+    pub retries: i32,
+    pub backoff_days: i32,
+    pub attempts: i32, // failed attempts at the current occurrence
+    pub occurrence: Option<i32>, // epoch day the run being retried was scheduled for
+
+    #[sqlx(try_from = "String")]
+    pub status: RunStatus, // of the latest run
+    pub consecutive_failures: i32, // runs that failed for good in a row
+    pub max_failures: i32, // pauses after this many consecutive failures, 0 to never pause
+    pub paused: bool,
+
+    // epoch days, inclusive
+    pub start_day: Option<i32>,
+    pub end_day: Option<i32>,
+    pub max_occurrences: Option<i32>,
+    pub occurrences: i32, // runs that are over with, successful or not
+    pub completed: bool, // past end_day or max_occurrences, kept around for history
+
+    #[sqlx(try_from = "String")]
+    pub catch_up: CatchUpSpecies,
+
+    pub priority: i32, // higher ones run first when several are due on the same day
+    pub partial: bool, // sends whatever the origin has when it cant cover the whole amount
+}
+
+// worked out when the transfer runs
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum AmountSpecies {
+    Fixed, // amount as is
+    Percentage, // amount percent of the origin's balance
+    Above, // everything in the origin above amount
+    TopUp, // whatever brings the destination up to amount
+}
+impl TryFrom<String> for AmountSpecies {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// what to do about runs missed while the daily tasks werent running
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum CatchUpSpecies {
+    Skip, // carry on from today
+    Once, // a single run makes up for all of them
+    Every, // every missed run happens late
+}
+impl TryFrom<String> for CatchUpSpecies {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScheduledRun {
+    pub day: i64, // epoch day
+    pub amount: f64, // anything but Fixed is estimated from the current balances
+}
+
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    Pending, // hasnt run yet
+    Succeeded,
+    Partial, // sent only part of the amount, the rest is in the log's shortfall
+    FailedRetrying,
+    FailedFinal,
+}
+impl TryFrom<String> for RunStatus {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
 }
 impl AutoTransfer {
     // tasks
     pub async fn increment_auto_transfers(db: &Pool<Postgres>) {
         // run once per day
-        let auto_transfers = sqlx::query_as::<_, AutoTransfer>("select * from plutus.auto_transfer where $1 - last_transfer >= duration;")
-            .bind(utils::get_epoch_day())
+        let today = utils::get_epoch_day();
+        let auto_transfers = sqlx::query_as::<_, AutoTransfer>("select * from plutus.auto_transfer where not paused and next_run <= $1 order by priority desc, id;")
+            .bind(today)
             .fetch_all(db)
             .await.unwrap();
 
-        for t in auto_transfers {
-            match Account::transfer(db, t.origin, t.destination, t.amount, false).await {
-                None => {
-                    Log::append(db, t.amount, Source::AutoTransfer(t.origin), Source::AutoTransfer(t.destination), Outcome::Success).await;
+        for mut t in auto_transfers {
+            // runs missed while the tasks werent running, retries are just late and dont count
+            let missed = t.occurrence.is_none() && t.next_run.is_some_and(|n| (n as i64) < today);
+
+            match t.catch_up {
+                CatchUpSpecies::Skip => if missed {
+                    t.skip_to(today);
                 },
-                Some(e) => {
-                    Log::append(db, t.amount, Source::AutoTransfer(t.origin), Source::AutoTransfer(t.destination), e).await;
+                CatchUpSpecies::Once => if missed {
+                    let catch_up = t.next_run;
+                    t.run(db, today, catch_up).await;
+                    // the one run covers everything up to today
+                    if t.occurrence.is_none() {
+                        t.skip_to(today + 1);
+                    }
+                },
+                CatchUpSpecies::Every => {
+                    while t.is_due(today) && t.occurrence.is_none() && t.next_run.is_some_and(|n| (n as i64) < today) {
+                        let catch_up = t.next_run;
+                        t.run(db, today, catch_up).await;
+                    }
                 }
             }
 
-            // TODO : consider if should still reset last transfer if fail?
-            sqlx::query("update plutus.auto_transfer set last_transfer = $1 where id = $2;")
-                .bind(utils::get_epoch_day())
-                .bind(t.id)
-                .execute(db)
-                .await.unwrap();
+            if t.is_due(today) {
+                t.run(db, today, None).await;
+            }
+
+            t.save_run(db).await;
         }
     }
     // 
 
+    // a single run on `day`, `catch_up` being the missed day it makes up for
+    async fn run(&mut self, db: &Pool<Postgres>, day: i64, catch_up: Option<i32>) {
+        let mut link = RunLink { auto_transfer: self.id, catch_up, shortfall: None };
 
-    pub async fn create(db: &Pool<Postgres>, origin: i64, destination: i64, amount: f64, duration: i32) {
-        sqlx::query("insert into plutus.auto_transfer(origin, destination, amount, duration, last_transfer) values ($1, $2, $3, $4, $5);")
-            .bind(origin)
-            .bind(destination)
-            .bind(amount)
-            .bind(duration)
-            .bind(utils::get_epoch_day())
+        if let Some(u) = self.destination_user.clone() {
+            match User::fetch(db, &u).await {
+                Some(u) => self.destination = u.default_account,
+                // logged against the account it last resolved to
+                None => return self.failed(db, day, self.amount, Source::AutoTransfer(self.destination), Outcome::Account(AccountError::NoExist), link).await
+            }
+        }
+
+        let amount = match (Account::fetch(db, self.origin).await, Account::fetch(db, self.destination).await) {
+            (Some(o), Some(d)) => {
+                let (amount, shortfall) = self.available(self.amount_for(o.balance, d.balance), o.balance);
+                link.shortfall = shortfall;
+                amount
+            },
+            _ => self.amount // let Account::transfer report it
+        };
+
+        // nothing to sweep this time
+        if amount <= 0f64 {
+            self.succeed(day);
+            return;
+        }
+
+        // successful transfers are logged by Account::transfer itself
+        match Account::transfer_linked(db, self.origin, self.destination, amount, Source::AutoTransfer, Some(link)).await {
+            None if link.shortfall.is_some() => self.succeed_partially(day),
+            None => self.succeed(day),
+            Some(e) => self.failed(db, day, amount, Source::AutoTransfer(self.destination), e, link).await
+        }
+    }
+
+    async fn failed(&mut self, db: &Pool<Postgres>, day: i64, amount: f64, destination: Source, e: Outcome, link: RunLink) {
+        Log::append_auto_transfer(db, amount, Source::AutoTransfer(self.origin), destination, e, link).await;
+
+        if self.fail(day) {
+            if let Some(a) = Account::fetch(db, self.origin).await {
+                Notification::push(db, &a.owner, NotificationSpecies::AutoTransferPaused, self.id, format!("auto transfer {} was paused after failing {} times in a row", self.id, self.consecutive_failures)).await;
+            }
+        }
+    }
+
+    // moves past every occurrence before `day` without running them
+    pub fn skip_to(&mut self, day: i64) {
+        while let Some(n) = self.next_run.filter(|n| (*n as i64) < day) {
+            self.last_occurrence = Some(n);
+            self.next_run = self.schedule.next(n as i64).map(|n| n as i32);
+        }
+        if self.next_run.is_none_or(|n| self.end_day.is_some_and(|e| n > e)) {
+            self.completed = true;
+            self.next_run = None;
+        }
+    }
+
+
+    // rounded down to the cent, can be 0 or less when there is nothing to move
+    pub fn amount_for(&self, origin_balance: f64, destination_balance: f64) -> f64 {
+        let amount = match self.amount_species {
+            AmountSpecies::Fixed => return self.amount,
+            AmountSpecies::Percentage => origin_balance * self.amount / 100f64,
+            AmountSpecies::Above => origin_balance - self.amount,
+            AmountSpecies::TopUp => self.amount - destination_balance
+        };
+        (amount * 100f64).floor() / 100f64
+    }
+
+    // what actually gets sent out of `amount` and how much short of it that is
+    // only partial ones send less, and only when there is something to send at all
+    pub fn available(&self, amount: f64, origin_balance: f64) -> (f64, Option<f64>) {
+        let balance = (origin_balance * 100f64).floor() / 100f64;
+        if self.partial && amount > balance && balance > 0f64 {
+            (balance, Some(((amount - balance) * 100f64).round() / 100f64))
+        } else {
+            (amount, None)
+        }
+    }
+
+    // the next `count` days (epoch days) this is going to run on, ignoring retries that might come up
+    pub fn upcoming(&self, count: usize) -> Vec<i64> {
+        let mut result = vec![];
+        if self.paused {
+            return result;
+        }
+
+        let remaining = self.max_occurrences.map_or(usize::MAX, |m| (m - self.occurrences).max(0) as usize);
+        let mut next = self.next_run.map(|n| n as i64);
+        // a retry is pending, the schedule carries on from what is being retried
+        let mut occurrence = self.occurrence.map(|o| o as i64).or(next);
+        while let Some(n) = next {
+            if result.len() >= count.min(remaining) || self.end_day.is_some_and(|e| n > e as i64) {
+                break;
+            }
+            result.push(n);
+
+            next = occurrence.and_then(|o| self.schedule.next(o));
+            occurrence = next;
+        }
+        result
+    }
+
+    // whether the daily tasks would run this on `day` (epoch day)
+    pub fn is_due(&self, day: i64) -> bool {
+        !self.paused && self.next_run.is_some_and(|n| n as i64 <= day)
+    }
+
+    // an occurrence is over with, moves on to `next` unless that is past the end
+    fn finish_occurrence(&mut self, occurrence: i64, next: Option<i64>) {
+        self.occurrences += 1;
+        self.last_occurrence = Some(occurrence as i32);
+        self.next_run = next.map(|n| n as i32);
+        self.check_completed();
+    }
+
+    // past max_occurrences or the next run is past the end
+    fn check_completed(&mut self) {
+        if self.max_occurrences.is_some_and(|m| self.occurrences >= m) || self.next_run.is_none_or(|n| self.end_day.is_some_and(|e| n > e)) {
+            self.completed = true;
+            self.next_run = None;
+        }
+    }
+
+    // works next_run out again when the schedule changed from `previous`, dropping any retry in progress
+    // carries on from the latest occurrence that is over with, but never from before `from`
+    // an unchanged schedule keeps the current run (and retries of it) as is
+    pub fn reschedule(&mut self, previous: &Schedule, from: i64) {
+        if self.schedule == *previous {
+            return;
+        }
+
+        let next = match (self.last_occurrence, self.start_day) {
+            (Some(l), _) => self.schedule.next(l as i64),
+            (None, Some(s)) => self.schedule.first_from(s as i64), // hasnt started yet
+            // nothing ran yet (last_transfer is when it was made) or from before last_occurrence was kept
+            (None, None) => self.schedule.next((self.last_transfer as i64).max(from - 1))
+        };
+        self.next_run = next.map(|n| n as i32);
+        self.attempts = 0;
+        self.occurrence = None;
+        self.skip_to(from);
+        self.check_completed();
+    }
+
+    // after running successfully on `day`
+    pub fn succeed(&mut self, day: i64) {
+        let occurrence = self.occurrence.or(self.next_run).map_or(day, |o| o as i64);
+
+        self.last_transfer = day as i32;
+        self.finish_occurrence(occurrence, self.schedule.next(occurrence));
+        self.attempts = 0;
+        self.occurrence = None;
+        self.status = RunStatus::Succeeded;
+        self.consecutive_failures = 0;
+    }
+
+    // after sending only part of the amount on `day`, the rest isnt retried
+    pub fn succeed_partially(&mut self, day: i64) {
+        self.succeed(day);
+        self.status = RunStatus::Partial;
+    }
+
+    // after failing on `day`, returns whether this got paused because of it
+    pub fn fail(&mut self, day: i64) -> bool {
+        let occurrence = self.occurrence.or(self.next_run).map_or(day, |o| o as i64);
+        let following = self.schedule.next(occurrence);
+
+        self.attempts += 1;
+        let retry = day + self.backoff_days as i64 * 2i64.pow((self.attempts - 1).clamp(0, 16) as u32);
+        // retries cant run into the next occurrence
+        if self.attempts <= self.retries && following.is_none_or(|f| retry < f) {
+            self.occurrence = Some(occurrence as i32);
+            self.next_run = Some(retry as i32);
+            self.status = RunStatus::FailedRetrying;
+            return false;
+        }
+
+        self.finish_occurrence(occurrence, following);
+        self.attempts = 0;
+        self.occurrence = None;
+        self.status = RunStatus::FailedFinal;
+        self.consecutive_failures += 1;
+
+        self.paused = self.max_failures > 0 && self.consecutive_failures >= self.max_failures;
+        self.paused
+    }
+
+    async fn save_run(&self, db: &Pool<Postgres>) {
+        sqlx::query("
+            update plutus.auto_transfer set
+                last_transfer = $1, next_run = $2, attempts = $3, occurrence = $4,
+                status = $5, consecutive_failures = $6, paused = $7, occurrences = $8, completed = $9,
+                destination = $10, last_occurrence = $11
+            where id = $12;
+        ")
+            .bind(self.last_transfer)
+            .bind(self.next_run)
+            .bind(self.attempts)
+            .bind(self.occurrence)
+            .bind(self.status.to_string())
+            .bind(self.consecutive_failures)
+            .bind(self.paused)
+            .bind(self.occurrences)
+            .bind(self.completed)
+            .bind(self.destination)
+            .bind(self.last_occurrence)
+            .bind(self.id)
+            .execute(db)
+            .await.unwrap();
+    }
+
+    pub async fn create(db: &Pool<Postgres>, candidate: AutoTransfer) {
+        sqlx::query("
+            insert into plutus.auto_transfer(
+                origin, destination, destination_user, amount, amount_species, schedule, last_transfer, next_run,
+                retries, backoff_days, status, max_failures, start_day, end_day, max_occurrences, catch_up,
+                priority, partial
+            ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18);
+        ")
+            .bind(candidate.origin)
+            .bind(candidate.destination)
+            .bind(candidate.destination_user.clone())
+            .bind(candidate.amount)
+            .bind(candidate.amount_species.to_string())
+            .bind(SqlJson(&candidate.schedule))
+            .bind(candidate.last_transfer)
+            .bind(candidate.next_run)
+            .bind(candidate.retries)
+            .bind(candidate.backoff_days)
+            .bind(candidate.status.to_string())
+            .bind(candidate.max_failures)
+            .bind(candidate.start_day)
+            .bind(candidate.end_day)
+            .bind(candidate.max_occurrences)
+            .bind(candidate.catch_up.to_string())
+            .bind(candidate.priority)
+            .bind(candidate.partial)
             .execute(db)
             .await.unwrap();
     }
 
-    pub async fn edit(db: &Pool<Postgres>, id: i64, amount: f64, duration: i32) {
-        sqlx::query("update plutus.auto_transfer set amount = $1, duration = $2 where id = $3;")
-            .bind(amount)
-            .bind(duration)
+    pub async fn pause(db: &Pool<Postgres>, id: i64) {
+        sqlx::query("update plutus.auto_transfer set paused = true where id = $1;")
             .bind(id)
             .execute(db)
             .await.unwrap();
     }
 
+    // carries on from the first run the daily tasks havent gone past yet
+    // runs missed while paused are dropped rather than caught up on, along with any retry in progress
+    pub async fn resume(db: &Pool<Postgres>, mut auto_transfer: AutoTransfer) {
+        let from = first_open_day(db).await;
+
+        if let Some(o) = auto_transfer.occurrence.take() {
+            auto_transfer.next_run = auto_transfer.schedule.next(o as i64).map(|n| n as i32);
+            auto_transfer.attempts = 0;
+        }
+        auto_transfer.skip_to(from);
+        auto_transfer.paused = false;
+        auto_transfer.consecutive_failures = 0;
+
+        auto_transfer.save_run(db).await;
+    }
+
+    // `edited` having been rescheduled already
+    pub async fn edit(db: &Pool<Postgres>, edited: &AutoTransfer) {
+        sqlx::query("
+            update plutus.auto_transfer set
+                amount = $1, amount_species = $2, schedule = $3, next_run = $4, attempts = $5, occurrence = $6, completed = $7,
+                priority = $8, partial = $9, last_occurrence = $10
+            where id = $11;
+        ")
+            .bind(edited.amount)
+            .bind(edited.amount_species.to_string())
+            .bind(SqlJson(&edited.schedule))
+            .bind(edited.next_run)
+            .bind(edited.attempts)
+            .bind(edited.occurrence)
+            .bind(edited.completed)
+            .bind(edited.priority)
+            .bind(edited.partial)
+            .bind(edited.last_occurrence)
+            .bind(edited.id)
+            .execute(db)
+            .await.unwrap();
+    }
+
     pub async fn fetch_incoming(db: &Pool<Postgres>, destination: i64) -> Vec<AutoTransfer> {
         sqlx::query_as::<_, AutoTransfer>("select * from plutus.auto_transfer where destination = $1;")
             .bind(destination)
@@ -110,7 +481,24 @@ pub enum AutoTransferError {
 
     TargetSame, // when to and from are the same
 
-    InsufficientBalance
+    InsufficientBalance,
+
+    Completed, // cant be resumed or edited anymore
+    TopUpNotOwned, // TopUp needs the destination's balance, so only into the user's own accounts
+}
+
+// first epoch day the daily tasks havent gone through yet
+async fn first_open_day(db: &Pool<Postgres>) -> i64 {
+    let today = utils::get_epoch_day();
+    if crate::last_incremented(db).await == today { today + 1 } else { today }
+}
+
+fn valid_amount(species: AmountSpecies, amount: f64) -> bool {
+    match species {
+        AmountSpecies::Percentage => amount > 0f64 && amount <= 100f64,
+        AmountSpecies::Above => amount >= 0f64,
+        AmountSpecies::Fixed | AmountSpecies::TopUp => amount > 0f64
+    }
 }
 
 pub async fn create(
@@ -120,16 +508,40 @@ pub async fn create(
 ) -> impl IntoResponse {
     utils::request_boiler(app_state, query, session_id, vec![
         ("origin", PlutusFormat::BigNumber),
-        ("destination", PlutusFormat::BigNumber),
         ("amount", PlutusFormat::Float),
-        ("duration", PlutusFormat::BigNumber),
     ], |db, session, query| async move {
+        // schedule args, see Schedule::from_query
+        // one of
+        // "destination" : account id
+        // "destination_user" : username, sends to whatever their default account is at the time
+        // optional args
+        // "retries" : how many times to retry a failed run before the next one is due
+        // "backoff_days" : days before the first retry, doubling after every retry
+        // "max_failures" : pauses after this many runs in a row failed for good, 0 to never pause
+        // "amount_species" : Fixed (default), Percentage, Above, TopUp, what "amount" means
+        // "start_day" : epoch day of the first possible run, otherwise it starts after today
+        // "end_day" : epoch day of the last possible run
+        // "max_occurrences" : completes after this many runs
+        // "catch_up" : Skip, Once (default), Every, for runs missed while the server was down
+        // "priority" : higher runs first among auto transfers due the same day, 0 by default
+        // "partial" : true to send whatever is available when the origin is short, false by default
         // check existance of both from and to
 
-        let destination = utils::from_query("destination", &query).parse::<i64>().unwrap();
+        let (destination, destination_user) = match (
+            utils::optional_query::<i64>("destination", &query),
+            utils::optional_query::<String>("destination_user", &query)
+        ) {
+            (Ok(Some(d)), Ok(None)) => (d, None),
+            (Ok(None), Ok(Some(u))) => match User::fetch(&db, &u).await {
+                Some(user) => (user.default_account, Some(u)),
+                None => return Outcome::AutoTransfer(AutoTransferError::ToDoesntExist)
+            },
+            (Ok(None), Ok(None)) => return Outcome::Plutus(PlutusError::InvalidArguments),
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
         let origin = utils::from_query("origin", &query).parse::<i64>().unwrap();
 
-        if !Account::is_owner(&db, origin, session.user).await {
+        if !Account::is_owner(&db, origin, session.user.clone()).await {
             // owns origin
             return Outcome::Account(AccountError::NoPermission);
         }
@@ -143,13 +555,78 @@ pub async fn create(
             return Outcome::AutoTransfer(AutoTransferError::TargetSame);
         }
 
-        AutoTransfer::create(
-            &db,
-            origin,
-            destination,
-            utils::from_query("amount", &query).parse::<f64>().unwrap(),
-            utils::from_query("duration", &query).parse::<i32>().unwrap(),
-        ).await;
+        let schedule = match Schedule::from_query(&query) {
+            Ok(s) => s,
+            Err(e) => return Outcome::Plutus(e)
+        };
+
+        let today = utils::get_epoch_day();
+        let (start_day, end_day, max_occurrences) = match (
+            utils::optional_query::<i32>("start_day", &query),
+            utils::optional_query::<i32>("end_day", &query),
+            utils::optional_query::<i32>("max_occurrences", &query)
+        ) {
+            (Ok(s), Ok(e), Ok(m)) if s.is_none_or(|s| s as i64 > today) && s.zip(e).is_none_or(|(s, e)| e >= s) && m.is_none_or(|m| m > 0) => (s, e, m),
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+        let next_run = match start_day {
+            Some(s) => schedule.first_from(s as i64),
+            None => schedule.next(today)
+        };
+        if next_run.is_none_or(|n| end_day.is_some_and(|e| n > e as i64)) {
+            // would never run
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+
+        let candidate = match (
+            utils::optional_query::<i32>("retries", &query),
+            utils::optional_query::<i32>("backoff_days", &query),
+            utils::optional_query::<i32>("max_failures", &query),
+            utils::optional_query::<AmountSpecies>("amount_species", &query),
+            utils::optional_query::<CatchUpSpecies>("catch_up", &query),
+            utils::optional_query::<i32>("priority", &query),
+            utils::optional_query::<bool>("partial", &query)
+        ) {
+            (Ok(retries), Ok(backoff_days), Ok(max_failures), Ok(amount_species), Ok(catch_up), Ok(priority), Ok(partial)) => AutoTransfer {
+                id: 0,
+                origin,
+                destination,
+                destination_user,
+                amount: utils::from_query("amount", &query).parse::<f64>().unwrap(),
+                amount_species: amount_species.unwrap_or(AmountSpecies::Fixed),
+                next_run: next_run.map(|n| n as i32),
+                last_occurrence: None,
+                schedule,
+                last_transfer: today as i32,
+                retries: retries.unwrap_or(DEFAULT_RETRIES),
+                backoff_days: backoff_days.unwrap_or(DEFAULT_BACKOFF_DAYS),
+                attempts: 0,
+                occurrence: None,
+                status: RunStatus::Pending,
+                consecutive_failures: 0,
+                max_failures: max_failures.unwrap_or(DEFAULT_MAX_FAILURES),
+                paused: false,
+                start_day,
+                end_day,
+                max_occurrences,
+                occurrences: 0,
+                completed: false,
+                catch_up: catch_up.unwrap_or(CatchUpSpecies::Once),
+                priority: priority.unwrap_or(0),
+                partial: partial.unwrap_or(false)
+            },
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        if candidate.retries < 0 || candidate.backoff_days < 1 || candidate.max_failures < 0 || !valid_amount(candidate.amount_species, candidate.amount) {
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+        // by username it only ever resolves to one of the user's own accounts if it is the user themself
+        if candidate.amount_species == AmountSpecies::TopUp && !Account::is_owner(&db, destination, session.user).await {
+            return Outcome::AutoTransfer(AutoTransferError::TopUpNotOwned);
+        }
+
+        AutoTransfer::create(&db, candidate).await;
 
         Outcome::Success
     }).await
@@ -163,26 +640,57 @@ pub async fn edit(
     utils::request_boiler(app_state, query, session_id, vec![
         ("auto_transfer", PlutusFormat::BigNumber),
         ("amount", PlutusFormat::Float),
-        ("duration", PlutusFormat::BigNumber),
     ], |db, session, query| async move {
+        // schedule args, see Schedule::from_query
+        // optional args
+        // "amount_species" : Fixed, Percentage, Above, TopUp, stays the same if left out
+        // "priority" : stays the same if left out
+        // "partial" : stays the same if left out
         let id = utils::from_query("auto_transfer", &query).parse::<i64>().unwrap();
 
         let auto_transfer = AutoTransfer::fetch(&db, id).await;
         if auto_transfer.is_none() {
             return Outcome::Account(AccountError::NoPermission);
         }
-        let auto_transfer = auto_transfer.unwrap();
+        let mut auto_transfer = auto_transfer.unwrap();
 
-        if !Account::is_owner(&db, auto_transfer.origin, session.user).await {
+        if !Account::is_owner(&db, auto_transfer.origin, session.user.clone()).await {
             return Outcome::Account(AccountError::NoPermission);
         }
 
-        AutoTransfer::edit(
-            &db,
-            id,
-            utils::from_query("amount", &query).parse::<f64>().unwrap(),
-            utils::from_query("duration", &query).parse::<i32>().unwrap(),
-        ).await;
+        if auto_transfer.completed {
+            return Outcome::AutoTransfer(AutoTransferError::Completed);
+        }
+
+        let schedule = match Schedule::from_query(&query) {
+            Ok(s) => s,
+            Err(e) => return Outcome::Plutus(e)
+        };
+
+        let amount = utils::from_query("amount", &query).parse::<f64>().unwrap();
+        let amount_species = match utils::optional_query::<AmountSpecies>("amount_species", &query) {
+            Ok(a) => a.unwrap_or(auto_transfer.amount_species),
+            Err(e) => return Outcome::Plutus(e)
+        };
+        if !valid_amount(amount_species, amount) {
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+        if amount_species == AmountSpecies::TopUp && !Account::is_owner(&db, auto_transfer.destination, session.user).await {
+            return Outcome::AutoTransfer(AutoTransferError::TopUpNotOwned);
+        }
+        let (priority, partial) = match (utils::optional_query::<i32>("priority", &query), utils::optional_query::<bool>("partial", &query)) {
+            (Ok(p), Ok(a)) => (p.unwrap_or(auto_transfer.priority), a.unwrap_or(auto_transfer.partial)),
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        auto_transfer.amount = amount;
+        auto_transfer.amount_species = amount_species;
+        let previous = std::mem::replace(&mut auto_transfer.schedule, schedule);
+        auto_transfer.priority = priority;
+        auto_transfer.partial = partial;
+        auto_transfer.reschedule(&previous, first_open_day(&db).await);
+
+        AutoTransfer::edit(&db, &auto_transfer).await;
 
         Outcome::Success
     }).await
@@ -241,3 +749,113 @@ pub async fn delete(
         }
     }).await
 }
+
+pub async fn resume(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("auto_transfer", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = utils::from_query("auto_transfer", &query).parse::<i64>().unwrap();
+
+        let auto_transfer = match AutoTransfer::fetch(&db, id).await {
+            Some(t) if Account::is_owner(&db, t.origin, session.user).await => t,
+            _ => return Outcome::Account(AccountError::NoPermission)
+        };
+        if auto_transfer.completed {
+            return Outcome::AutoTransfer(AutoTransferError::Completed);
+        }
+
+        AutoTransfer::resume(&db, auto_transfer).await;
+
+        Outcome::Success
+    }).await
+}
+
+pub async fn pause(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("auto_transfer", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = utils::from_query("auto_transfer", &query).parse::<i64>().unwrap();
+
+        match AutoTransfer::fetch(&db, id).await {
+            Some(t) if Account::is_owner(&db, t.origin, session.user).await => {
+                if t.completed {
+                    return Outcome::AutoTransfer(AutoTransferError::Completed);
+                }
+            },
+            _ => return Outcome::Account(AccountError::NoPermission)
+        }
+
+        AutoTransfer::pause(&db, id).await;
+
+        Outcome::Success
+    }).await
+}
+
+// runs of an auto transfer, newest first
+pub async fn history(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("auto_transfer", PlutusFormat::BigNumber),
+        ("amount", PlutusFormat::Number)
+    ], |db, session, query| async move {
+        // optional args
+        // same as /log/fetch
+        let id = utils::from_query("auto_transfer", &query).parse::<i64>().unwrap();
+
+        let auto_transfer = match AutoTransfer::fetch(&db, id).await {
+            Some(t) if Account::is_owner(&db, t.origin, session.user).await => t,
+            _ => return Outcome::Account(AccountError::NoPermission)
+        };
+
+        let mut filter = match LogFilter::from_query(&query) {
+            Ok(f) => f,
+            Err(e) => return Outcome::Plutus(e)
+        };
+        filter.auto_transfer = Some(id);
+
+        let amount = utils::from_query("amount", &query).parse::<i32>().unwrap().clamp(1, 100);
+
+        Outcome::Data(serde_json::to_string(&LogPage::fetch(&db, auto_transfer.origin, amount, &filter).await).unwrap())
+    }).await
+}
+
+pub async fn preview(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("auto_transfer", PlutusFormat::BigNumber),
+        ("count", PlutusFormat::Number)
+    ], |db, session, query| async move {
+        let id = utils::from_query("auto_transfer", &query).parse::<i64>().unwrap();
+
+        let auto_transfer = match AutoTransfer::fetch(&db, id).await {
+            Some(t) if Account::is_owner(&db, t.origin, session.user).await => t,
+            _ => return Outcome::Account(AccountError::NoPermission)
+        };
+
+        let amount = match (Account::fetch(&db, auto_transfer.origin).await, Account::fetch(&db, auto_transfer.destination).await) {
+            (Some(o), Some(d)) => auto_transfer.amount_for(o.balance, d.balance),
+            _ => auto_transfer.amount
+        };
+
+        let count = utils::from_query("count", &query).parse::<i32>().unwrap().clamp(1, MAX_PREVIEW as i32) as usize;
+        let runs = auto_transfer.upcoming(count).into_iter()
+            .map(|day| ScheduledRun { day, amount })
+            .collect::<Vec<ScheduledRun>>();
+
+        Outcome::Data(serde_json::to_string(&runs).unwrap())
+    }).await
+}
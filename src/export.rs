@@ -0,0 +1,194 @@
+// exporting account history into formats desktop finance tools can import
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use sqlx::{Pool, Postgres};
+
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{self, Log, Source}, plutus_error::{Outcome, PlutusFormat}, session::RawSessionID, utils::{self, Download}, AppState};
+
+const CURRENCY: &str = "USD";
+const OFX_NAME_LENGTH: usize = 32;
+
+// history of "account" between "from" and "to", along with the balance at "to"
+async fn fetch_statement(db: &Pool<Postgres>, query: &HashMap<String, String>, user: String) -> Result<(Account, Vec<Log>, f64), Outcome> {
+    let id = log::owned_account(db, query, user).await?;
+    let account = match Account::fetch(db, id).await {
+        Some(a) => a,
+        None => return Err(Outcome::Account(AccountError::NoExist))
+    };
+
+    let from = utils::from_query("from", query).parse::<i64>().unwrap();
+    let to = utils::from_query("to", query).parse::<i64>().unwrap();
+
+    // walk back from the current balance to get the balance at the end of the range
+    let closing_balance = account.balance - Log::fetch_range(db, id, to.saturating_add(1), i64::MAX).await
+        .iter().map(|l| l.signed_amount(id)).sum::<f64>();
+
+    Ok((account, Log::fetch_range(db, id, from, to).await, closing_balance))
+}
+
+fn counterparty(log: &Log, account: i64) -> String {
+    let other = if log.origin.account() == Some(account) { &log.destination } else { &log.origin };
+    match other {
+        Source::Bank => "bank".to_string(),
+        Source::User(a) => format!("account {a}"),
+        Source::AutoTransfer(a) => format!("auto transfer with account {a}"),
+        Source::RoundUp(a) => format!("round up with account {a}"),
+        Source::Rule(a) => format!("transfer rule with account {a}"),
+        Source::Import(a) | Source::ImportPosted(a) => format!("import into account {a}"),
+        Source::External(d) => d.clone()
+    }
+}
+
+// counterparties can be anything an import had in it
+// <NAME> is at most 32 characters, cut before escaping so an escape never gets split
+fn ofx_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(OFX_NAME_LENGTH)
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            c => c.to_string()
+        })
+        .collect()
+}
+
+// a line break would start a new field, or end the record early
+fn qif_field(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+pub fn to_ofx(account: &Account, logs: &[Log], from: i64, to: i64, closing_balance: f64) -> String {
+    // ofx 1.02 (sgml), leaf elements are left unclosed as per the spec
+    let mut transactions = String::new();
+    for l in logs.iter().filter(|l| l.affects_balance()) {
+        let amount = l.signed_amount(account.id);
+        transactions.push_str(&format!(
+            "<STMTTRN>\n<TRNTYPE>{}\n<DTPOSTED>{}\n<TRNAMT>{:.2}\n<FITID>{}\n<NAME>{}\n</STMTTRN>\n",
+            if amount < 0f64 { "DEBIT" } else { "CREDIT" },
+            utils::format_timestamp_compact(l.timestamp as i64),
+            amount,
+            l.id,
+            ofx_name(&counterparty(l, account.id))
+        ));
+    }
+
+    let now = utils::format_timestamp_compact(utils::get_time());
+    format!("OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+SECURITY:NONE
+ENCODING:USASCII
+CHARSET:1252
+COMPRESSION:NONE
+OLDFILEUID:NONE
+NEWFILEUID:NONE
+
+<OFX>
+<SIGNONMSGSRSV1>
+<SONRS>
+<STATUS>
+<CODE>0
+<SEVERITY>INFO
+</STATUS>
+<DTSERVER>{now}
+<LANGUAGE>ENG
+</SONRS>
+</SIGNONMSGSRSV1>
+<BANKMSGSRSV1>
+<STMTTRNRS>
+<TRNUID>0
+<STATUS>
+<CODE>0
+<SEVERITY>INFO
+</STATUS>
+<STMTRS>
+<CURDEF>{CURRENCY}
+<BANKACCTFROM>
+<BANKID>PLUTUS
+<ACCTID>{}
+<ACCTTYPE>CHECKING
+</BANKACCTFROM>
+<BANKTRANLIST>
+<DTSTART>{}
+<DTEND>{}
+{transactions}</BANKTRANLIST>
+<LEDGERBAL>
+<BALAMT>{closing_balance:.2}
+<DTASOF>{}
+</LEDGERBAL>
+</STMTRS>
+</STMTTRNRS>
+</BANKMSGSRSV1>
+</OFX>
+",
+        account.id,
+        utils::format_timestamp_compact(from),
+        utils::format_timestamp_compact(to),
+        utils::format_timestamp_compact(to)
+    )
+}
+
+pub fn to_qif(account: &Account, logs: &[Log]) -> String {
+    let mut result = "!Type:Bank\n".to_string();
+    for l in logs.iter().filter(|l| l.affects_balance()) {
+        let (y, m, d) = utils::civil_from_epoch_day((l.timestamp as i64).div_euclid(86400));
+        result.push_str(&format!(
+            "D{m:02}/{d:02}/{y:04}\nT{:.2}\nN{}\nP{}\n^\n",
+            l.signed_amount(account.id),
+            l.id,
+            qif_field(&counterparty(l, account.id))
+        ));
+    }
+    result
+}
+
+pub async fn ofx(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler_download(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("from", PlutusFormat::BigNumber),
+        ("to", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let (account, logs, closing_balance) = fetch_statement(&db, &query, session.user).await?;
+
+        Ok(Download {
+            filename: format!("{}.ofx", account.id),
+            content_type: "application/x-ofx",
+            body: to_ofx(
+                &account,
+                &logs,
+                utils::from_query("from", &query).parse::<i64>().unwrap(),
+                utils::from_query("to", &query).parse::<i64>().unwrap(),
+                closing_balance
+            )
+        })
+    }).await
+}
+
+pub async fn qif(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler_download(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("from", PlutusFormat::BigNumber),
+        ("to", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let (account, logs, _) = fetch_statement(&db, &query, session.user).await?;
+
+        Ok(Download {
+            filename: format!("{}.qif", account.id),
+            content_type: "application/qif",
+            body: to_qif(&account, &logs)
+        })
+    }).await
+}
@@ -0,0 +1,210 @@
+// projecting balances forward using the auto transfers that are set up
+// mirrors what the daily tasks would do, assuming nothing else moves money in the meantime
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::{account::{Account, AccountError}, auto_transfer::{AmountSpecies, AutoTransfer, CatchUpSpecies}, extractor_error::ExtractorError, limit::{Limit, LimitSpecies}, plutus_error::{Outcome, PlutusFormat}, session::RawSessionID, user::User, utils, AppState};
+
+const MAX_DAYS: i64 = 366;
+
+#[derive(Serialize, Deserialize)]
+pub struct ForecastFailure {
+    pub day: i64, // epoch day
+    pub auto_transfer: i64,
+    pub reason: Outcome,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ForecastShortfall {
+    pub day: i64, // epoch day
+    pub auto_transfer: i64,
+    pub shortfall: f64, // what a partial run couldnt send
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountForecast {
+    pub account: i64,
+    pub balances: Vec<f64>, // end of day balance, one per day starting from Forecast.start
+    pub first_failure: Option<ForecastFailure>,
+    pub first_shortfall: Option<ForecastShortfall>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Forecast {
+    pub start: i64, // epoch day
+    pub accounts: Vec<AccountForecast>,
+    pub unprojectable: Vec<i64>, // auto transfers left out, their amount depends on the balance of an account that isnt the user's
+}
+
+pub async fn project(db: &Pool<Postgres>, user: String, days: i64) -> Forecast {
+    let accounts = Account::fetch_all(db, user).await;
+    let owned = accounts.iter().map(|a| a.id).collect::<Vec<i64>>();
+
+    let mut auto_transfers: Vec<AutoTransfer> = vec![];
+    let mut limits: Vec<Limit> = vec![];
+    for a in &owned {
+        for t in AutoTransfer::fetch_outgoing(db, *a).await.into_iter().chain(AutoTransfer::fetch_incoming(db, *a).await) {
+            if !auto_transfers.iter().any(|x| x.id == t.id) {
+                auto_transfers.push(t);
+            }
+        }
+    }
+    // user wide limits show up for every account
+    for a in &accounts {
+        for l in Limit::fetch_all(db, a).await {
+            if !limits.iter().any(|x| x.id == l.id) {
+                limits.push(l);
+            }
+        }
+    }
+    // same order the daily tasks run them in
+    auto_transfers.sort_by_key(|t| (-t.priority, t.id));
+
+    // (epoch seconds, amount) counted by each rolling limit, to sum over as the window moves
+    let mut sent: HashMap<i64, Vec<(i64, f64)>> = HashMap::new();
+    for l in limits.iter().filter(|l| l.rolling) {
+        sent.insert(l.id, l.spent_since(db, l.window_start(utils::get_time())).await);
+    }
+
+    // by username ones go wherever the recipient's default account is now
+    for t in auto_transfers.iter_mut() {
+        if let Some(u) = t.destination_user.clone() {
+            if let Some(u) = User::fetch(db, &u).await {
+                t.destination = u.default_account;
+            }
+        }
+    }
+
+    let mut destinations: HashMap<i64, Account> = HashMap::new();
+    for t in &auto_transfers {
+        if let Some(a) = Account::fetch(db, t.destination).await {
+            destinations.insert(a.id, a);
+        }
+    }
+    let owners = accounts.iter().map(|a| (a.id, a)).collect::<HashMap<i64, &Account>>();
+
+    // todays run might not have happened yet
+    let start = if crate::last_incremented(db).await == utils::get_epoch_day() { utils::get_epoch_day() + 1 } else { utils::get_epoch_day() };
+
+    // runs missed before the forecast starts that would just be dropped
+    for t in auto_transfers.iter_mut().filter(|t| t.catch_up == CatchUpSpecies::Skip && t.occurrence.is_none()) {
+        t.skip_to(start);
+    }
+
+    let mut result = accounts.iter().map(|a| AccountForecast { account: a.id, balances: vec![], first_failure: None, first_shortfall: None }).collect::<Vec<AccountForecast>>();
+    let mut balances = accounts.iter().map(|a| (a.id, a.balance)).collect::<HashMap<i64, f64>>();
+    let mut unprojectable = vec![];
+
+    for day in start..(start + days) {
+        // same order as increment_tasks, limits reset first
+        limits.retain(|l| !(l.pending_delete && l.is_pending_due(day)));
+        for l in limits.iter_mut().filter(|l| l.is_pending_due(day)) {
+            l.settle_pending();
+        }
+        for l in limits.iter_mut().filter(|l| l.is_due(day)) {
+            l.usage = 0f64;
+            l.last_enforcement = l.window_reset_day(day);
+        }
+        for l in limits.iter_mut().filter(|l| l.rolling) {
+            let since = l.window_start(day * 86400);
+            let window = sent[&l.id].iter().filter(|(t, _)| *t > since);
+            l.usage = match l.species {
+                LimitSpecies::Count => window.count() as f64,
+                _ => window.map(|(_, a)| a).sum()
+            };
+        }
+
+        for t in auto_transfers.iter_mut().filter(|t| t.is_due(day)) {
+            let amount = match (balances.get(&t.origin), destinations.get(&t.destination)) {
+                (Some(o), Some(d)) => t.amount_for(*o, balances.get(&d.id).copied().unwrap_or(d.balance)),
+                (Some(_), None) => t.amount, // fails below, nothing gets moved
+                (None, Some(d)) if matches!(t.amount_species, AmountSpecies::Fixed | AmountSpecies::TopUp) => t.amount_for(0f64, balances.get(&d.id).copied().unwrap_or(d.balance)),
+                _ => {
+                    if !unprojectable.contains(&t.id) {
+                        unprojectable.push(t.id);
+                    }
+                    continue;
+                }
+            };
+            if amount <= 0f64 {
+                t.succeed(day);
+                continue;
+            }
+            let (amount, shortfall) = match balances.get(&t.origin) {
+                Some(o) => t.available(amount, *o),
+                None => (amount, None)
+            };
+            // accounts that arent the user's are assumed to always be able to pay
+            let (failure, applicable) = match (owners.get(&t.origin), destinations.get(&t.destination)) {
+                (None, _) => (None, vec![]),
+                (Some(_), None) => (Some(Outcome::Account(AccountError::NoExist)), vec![]),
+                (Some(_), Some(_)) if balances[&t.origin] < amount => (Some(Outcome::Account(AccountError::InsufficientBalance)), vec![]),
+                (Some(o), Some(d)) => (
+                    Limit::check_all(&limits, o, d, amount).map(Outcome::Limit),
+                    limits.iter().filter(|l| l.applies_to(o, d)).map(|l| l.id).collect::<Vec<i64>>()
+                )
+            };
+
+            match failure {
+                None => {
+                    match shortfall {
+                        Some(shortfall) => {
+                            t.succeed_partially(day);
+                            let f = result.iter_mut().find(|f| f.account == t.origin).unwrap();
+                            if f.first_shortfall.is_none() {
+                                f.first_shortfall = Some(ForecastShortfall { day, auto_transfer: t.id, shortfall });
+                            }
+                        },
+                        None => t.succeed(day)
+                    }
+                    if let Some(b) = balances.get_mut(&t.origin) {
+                        *b -= amount;
+                    }
+                    if let Some(b) = balances.get_mut(&t.destination) {
+                        *b += amount;
+                    }
+                    // rolling ones get recomputed from `sent` the next day anyway
+                    for l in limits.iter_mut().filter(|l| applicable.contains(&l.id) && l.species != LimitSpecies::Transaction) {
+                        l.usage += if l.species == LimitSpecies::Count { 1f64 } else { amount };
+                        if let Some(s) = sent.get_mut(&l.id) {
+                            s.push((day * 86400, amount));
+                        }
+                    }
+                },
+                Some(reason) => {
+                    // retries and pausing play out the same way they would for real
+                    t.fail(day);
+                    let f = result.iter_mut().find(|f| f.account == t.origin).unwrap();
+                    if f.first_failure.is_none() {
+                        f.first_failure = Some(ForecastFailure { day, auto_transfer: t.id, reason });
+                    }
+                }
+            }
+        }
+
+        for f in result.iter_mut() {
+            f.balances.push(balances[&f.account]);
+        }
+    }
+
+    Forecast { start, accounts: result, unprojectable }
+}
+
+pub async fn fetch(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("days", PlutusFormat::Number)
+    ], |db, session, query| async move {
+        let days = utils::from_query("days", &query).parse::<i64>().unwrap().clamp(1, MAX_DAYS);
+
+        Outcome::Data(serde_json::to_string(&project(&db, session.user, days).await).unwrap())
+    }).await
+}
@@ -0,0 +1,394 @@
+// importing cash/external bank transactions (from spreadsheets) into the log
+// imported rows are kept as Source::Import and dont touch the balance until posted
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{self, Log, Source}, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+#[derive(Serialize, Deserialize)]
+pub struct RawImport { // used ONLY for the json payload
+    pub id: String, // session id
+    pub csv: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportRow {
+    pub line: usize,
+    pub timestamp: i64,
+    pub amount: f64, // negative -> leaving the account
+    pub description: String,
+    pub duplicate: bool, // already imported before or earlier in the same file, will be skipped
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub rows: Vec<ImportRow>,
+    pub errors: Vec<(usize, ImportError)>, // (line, error)
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum DateFormat {
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ImportError {
+    // whole file
+    Empty,
+    ColumnNoExist,
+
+    // per row
+    MissingColumn,
+    InvalidDate,
+    InvalidAmount,
+
+    // posting
+    ImportNoExist,
+    AlreadyPosted,
+}
+
+// splits csv text into rows of fields, handling quoted fields and "" escapes
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                },
+                '"' => quoted = false,
+                _ => field.push(c)
+            }
+            continue;
+        }
+
+        match c {
+            '"' => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' => {},
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ => field.push(c)
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    // skip blank lines
+    rows.into_iter().filter(|r| !(r.len() == 1 && r[0].trim().is_empty())).collect()
+}
+
+// epoch seconds at the start of that day (utc)
+pub fn parse_date(raw: &str, format: DateFormat) -> Option<i64> {
+    let parts = raw.trim().split(['-', '/', '.']).map(|x| x.trim().parse::<i64>()).collect::<Result<Vec<i64>, _>>().ok()?;
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let (year, month, day) = match format {
+        DateFormat::YearMonthDay => (parts[0], parts[1], parts[2]),
+        DateFormat::DayMonthYear => (parts[2], parts[1], parts[0]),
+        DateFormat::MonthDayYear => (parts[2], parts[0], parts[1]),
+    };
+
+    if !(1..=12).contains(&month) || day < 1 || day > utils::days_in_month(year, month as u32) as i64 {
+        return None;
+    }
+
+    Some(utils::epoch_day_from_civil(year, month as u32, day as u32) * 86400)
+}
+
+pub fn parse_amount(raw: &str) -> Option<f64> {
+    // "1,234.50", "$ 12", "-3"
+    let cleaned = raw.chars().filter(|c| !(c.is_whitespace() || *c == ',' || *c == '$')).collect::<String>();
+    match cleaned.parse::<f64>() {
+        Ok(a) if a.is_finite() && a != 0f64 => Some(a),
+        _ => None
+    }
+}
+
+// header name (non case-sensitive) or zero based index
+fn find_column(header: &[String], key: &str) -> Option<usize> {
+    match key.parse::<usize>() {
+        Ok(i) => if i < header.len() { Some(i) } else { None },
+        Err(_) => header.iter().position(|h| h.trim().eq_ignore_ascii_case(key.trim()))
+    }
+}
+
+pub async fn preview(db: &Pool<Postgres>, account: i64, csv: &str, columns: (&str, &str, &str), format: DateFormat) -> Result<ImportPreview, ImportError> {
+    let rows = parse_csv(csv);
+    if rows.is_empty() {
+        return Err(ImportError::Empty);
+    }
+
+    let header = &rows[0];
+    let (date, amount, description) = match (find_column(header, columns.0), find_column(header, columns.1), find_column(header, columns.2)) {
+        (Some(d), Some(a), Some(n)) => (d, a, n),
+        _ => return Err(ImportError::ColumnNoExist)
+    };
+
+    // everything imported into this account before, to spot duplicates
+    let mut existing = Log::fetch_range(db, account, i64::MIN, i64::MAX).await
+        .into_iter()
+        .filter_map(|l| import_key(&l, account))
+        .collect::<Vec<(i64, f64, String)>>();
+
+    let mut result = ImportPreview { rows: vec![], errors: vec![] };
+    // line numbers are 1 based, header being line 1
+    for (line, row) in rows.iter().enumerate().skip(1).map(|(i, r)| (i + 1, r)) {
+        let (raw_date, raw_amount, raw_description) = match (row.get(date), row.get(amount), row.get(description)) {
+            (Some(d), Some(a), Some(n)) => (d, a, n),
+            _ => {
+                result.errors.push((line, ImportError::MissingColumn));
+                continue;
+            }
+        };
+
+        let timestamp = match parse_date(raw_date, format) {
+            Some(t) => t,
+            None => {
+                result.errors.push((line, ImportError::InvalidDate));
+                continue;
+            }
+        };
+
+        let amount = match parse_amount(raw_amount) {
+            Some(a) => a,
+            None => {
+                result.errors.push((line, ImportError::InvalidAmount));
+                continue;
+            }
+        };
+
+        let description = raw_description.trim().to_string();
+        let key = (timestamp.div_euclid(86400), amount, description.clone());
+        let duplicate = existing.contains(&key);
+        if !duplicate {
+            existing.push(key);
+        }
+
+        result.rows.push(ImportRow { line, timestamp, amount, description, duplicate });
+    }
+
+    Ok(result)
+}
+
+// (epoch day, signed amount, description) of a previously imported log
+// logs made by posting are left out, the imported log they point at is still there
+fn import_key(log: &Log, account: i64) -> Option<(i64, f64, String)> {
+    if log.posts.is_some() {
+        return None;
+    }
+
+    let day = (log.timestamp as i64).div_euclid(86400);
+    match (&log.origin, &log.destination) {
+        (Source::Import(a) | Source::ImportPosted(a), Source::External(d)) if *a == account => Some((day, -log.balance, d.clone())),
+        (Source::External(d), Source::Import(a) | Source::ImportPosted(a)) if *a == account => Some((day, log.balance, d.clone())),
+        _ => None
+    }
+}
+
+// all rows or none of them
+pub async fn commit(db: &Pool<Postgres>, account: i64, preview: &ImportPreview) {
+    let mut tx = db.begin().await.unwrap();
+    for row in preview.rows.iter().filter(|r| !r.duplicate) {
+        let (origin, destination) = if row.amount < 0f64 {
+            (Source::Import(account), Source::External(row.description.clone()))
+        } else {
+            (Source::External(row.description.clone()), Source::Import(account))
+        };
+
+        Log::append_at(&mut *tx, row.amount.abs(), origin, destination, Outcome::Success, row.timestamp).await;
+    }
+    tx.commit().await.unwrap();
+}
+
+// applies an imported row onto the real balance, as a new log at the time of posting
+// the imported row stays as it is, so balances replayed from the log agree on when the money moved
+pub async fn post(db: &Pool<Postgres>, account: i64, id: i64) -> Option<Outcome> {
+    let l = match Log::fetch_by_id(db, id).await {
+        Some(l) => l,
+        None => return Some(Outcome::Import(ImportError::ImportNoExist))
+    };
+
+    let amount = match import_key(&l, account) {
+        Some((_, a, _)) => a,
+        None => return Some(Outcome::Import(ImportError::ImportNoExist))
+    };
+
+    if l.origin == Source::ImportPosted(account) || l.destination == Source::ImportPosted(account) {
+        return Some(Outcome::Import(ImportError::AlreadyPosted));
+    }
+
+    if Account::fetch(db, account).await.is_none() {
+        return Some(Outcome::Account(AccountError::NoExist));
+    }
+
+    // the balance and the log change together or not at all
+    let mut tx = db.begin().await.unwrap();
+    // locks the imported row so it cant be posted twice at once
+    sqlx::query("select id from plutus.log where id = $1 for update;")
+        .bind(id)
+        .execute(&mut *tx)
+        .await.unwrap();
+    let posted_before = sqlx::query("select id from plutus.log where posts = $1;")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await.unwrap();
+    if posted_before.is_some() {
+        return Some(Outcome::Import(ImportError::AlreadyPosted));
+    }
+
+    let balance = match sqlx::query("update plutus.account set balance = balance + $1 where id = $2 and balance + $1 >= 0 returning balance;")
+        .bind(amount)
+        .bind(account)
+        .fetch_optional(&mut *tx)
+        .await.unwrap() {
+        Some(r) => r.get::<f64, usize>(0),
+        None => return Some(Outcome::Account(AccountError::InsufficientBalance))
+    };
+
+    // only the account side has a balance to record
+    let (origin_balance, destination_balance) = if amount < 0f64 { (Some(balance), None) } else { (None, Some(balance)) };
+    let posted = |s: Source| if s == Source::Import(account) { Source::ImportPosted(account) } else { s };
+    Log::append_posted(&mut tx, l.balance, posted(l.origin), posted(l.destination), (origin_balance, destination_balance), id).await;
+
+    tx.commit().await.unwrap();
+    None
+}
+
+pub async fn csv(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(payload), _): WithRejection<Json<RawImport>, ExtractorError>
+) -> impl IntoResponse {
+    // optional args
+    // "date_format" : YearMonthDay (default), DayMonthYear, MonthDayYear
+    // "dry_run" : true -> only preview, nothing gets imported
+    let csv = payload.csv;
+    utils::request_boiler(app_state, query, RawSessionID { id: payload.id }, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("date", PlutusFormat::Unspecified),
+        ("amount", PlutusFormat::Unspecified),
+        ("description", PlutusFormat::Unspecified)
+    ], move |db, session, query| {
+        let csv = csv.clone();
+        async move {
+            let id = match log::owned_account(&db, &query, session.user).await {
+                Ok(i) => i,
+                Err(e) => return e
+            };
+
+            let format = match query.get("date_format").map(|x| x.as_str()) {
+                None | Some("YearMonthDay") => DateFormat::YearMonthDay,
+                Some("DayMonthYear") => DateFormat::DayMonthYear,
+                Some("MonthDayYear") => DateFormat::MonthDayYear,
+                _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+            };
+
+            let result = match preview(
+                &db,
+                id,
+                &csv,
+                (
+                    &utils::from_query("date", &query),
+                    &utils::from_query("amount", &query),
+                    &utils::from_query("description", &query)
+                ),
+                format
+            ).await {
+                Ok(p) => p,
+                Err(e) => return Outcome::Import(e)
+            };
+
+            if query.get("dry_run").map(|x| x.as_str()) != Some("true") {
+                commit(&db, id, &result).await;
+            }
+
+            Outcome::Data(serde_json::to_string(&result).unwrap())
+        }
+    }).await
+}
+
+pub async fn post_import(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("log", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        match post(&db, id, utils::from_query("log", &query).parse::<i64>().unwrap()).await {
+            Some(e) => e,
+            None => Outcome::Success
+        }
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoted_fields() {
+        let rows = parse_csv("date,amount,description\n2025-01-02,\"1,234.50\",\"Shop, \"\"The\"\"\"\n");
+        assert_eq!(rows, vec![
+            vec!["date", "amount", "description"],
+            vec!["2025-01-02", "1,234.50", "Shop, \"The\""]
+        ]);
+    }
+
+    #[test]
+    fn csv_embedded_newlines() {
+        let rows = parse_csv("a,b\r\n1,\"two\nlines\"\r\n\r\n3,4");
+        assert_eq!(rows, vec![
+            vec!["a", "b"],
+            vec!["1", "two\nlines"],
+            vec!["3", "4"]
+        ]);
+    }
+
+    #[test]
+    fn dates() {
+        let expected = Some(utils::epoch_day_from_civil(2024, 2, 29) * 86400);
+        assert_eq!(parse_date("2024-02-29", DateFormat::YearMonthDay), expected);
+        assert_eq!(parse_date("29/02/2024", DateFormat::DayMonthYear), expected);
+        assert_eq!(parse_date(" 02.29.2024 ", DateFormat::MonthDayYear), expected);
+
+        assert_eq!(parse_date("2023-02-29", DateFormat::YearMonthDay), None);
+        assert_eq!(parse_date("2024-13-01", DateFormat::YearMonthDay), None);
+        assert_eq!(parse_date("2024-01", DateFormat::YearMonthDay), None);
+        assert_eq!(parse_date("yesterday", DateFormat::YearMonthDay), None);
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_amount("$ 1,234.50"), Some(1234.5));
+        assert_eq!(parse_amount("-3"), Some(-3f64));
+        assert_eq!(parse_amount("0"), None);
+        assert_eq!(parse_amount("abc"), None);
+    }
+}
@@ -1,113 +1,409 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, env};
 
 use axum::{extract::{Query, State}, response::IntoResponse, Json};
 use axum_extra::extract::WithRejection;
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, Pool, Postgres, Row};
+use strum_macros::{Display, EnumString};
 
-use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, plutus_error::{PlutusFormat, Outcome}, session::RawSessionID, utils, AppState};
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{self, SourceSpecies}, notification::{Notification, NotificationSpecies}, plutus_error::{PlutusError, PlutusFormat, Outcome}, session::RawSessionID, utils, AppState};
 
-#[derive(FromRow, Serialize, Deserialize)]
+#[derive(FromRow, Serialize, Deserialize, Clone)]
 pub struct Limit {
     pub id: i64,
-    pub account: i64,
+    // exactly one of these is set
+    pub account: Option<i64>, // only transfers out of this account
+    pub owner: Option<String>, // transfers out of any of this user's accounts
 
     pub usage: f64,
     pub cap: f64,
 
-    pub duration: i32,
+    pub duration: i32, // calendar limits, usage resets every x number of days
     pub last_enforcement: i32,
+
+    pub rolling: bool, // usage is computed from the log instead, over the last window_hours
+    pub window_hours: i32,
+
+    #[sqlx(try_from = "String")]
+    pub species: LimitSpecies,
+    // only count transfers going to this account/user
+    pub counterparty_account: Option<i64>,
+    pub counterparty_user: Option<String>,
+    pub exempt_own: bool, // transfers between accounts of the same user dont count
+
+    // loosening a limit only takes effect after a cooling off period
+    pub pending_cap: Option<f64>,
+    pub pending_duration: Option<i32>,
+    pub pending_window_hours: Option<i32>,
+    pub pending_delete: bool,
+    pub pending_effective: Option<i32>, // epoch day the pending change gets applied on
+
+    pub thresholds: Vec<f64>, // percentages of cap to notify the user at
+    pub alerted: Vec<f64>, // thresholds already notified about this window
+}
+
+const DEFAULT_COOLING_OFF_DAYS: i64 = 2;
+
+// days before raising/deleting a limit takes effect
+pub fn cooling_off_days() -> i64 {
+    env::var("LIMIT_COOLING_OFF_DAYS").ok().and_then(|d| d.parse::<i64>().ok()).unwrap_or(DEFAULT_COOLING_OFF_DAYS)
+}
+
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum LimitSpecies {
+    Cumulative, // usage adds up until the window resets
+    Transaction, // cap is the most a single transfer can be
+    Count, // cap is how many transfers can be made until the window resets
+}
+impl TryFrom<String> for LimitSpecies {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
 }
 
 impl Limit {
     // tasks
     pub async fn increment_limits(db: &Pool<Postgres>) {
         // run once per day
-        let limits = sqlx::query_as::<_, Limit>("select * from plutus.limit where $1 - last_enforcement >= duration;")
+        let limits = sqlx::query_as::<_, Limit>("select * from plutus.limit where not rolling and $1 - last_enforcement >= duration;")
             .bind(utils::get_epoch_day())
             .fetch_all(db)
             .await.unwrap();
 
         for limit in limits {
             // use transaction/some multi-query structure?
-            sqlx::query("update plutus.limit set last_enforcement = $1, usage = 0 where id = $2;")
-                .bind(utils::get_epoch_day())
+            sqlx::query("update plutus.limit set last_enforcement = $1, usage = 0, alerted = '{}' where id = $2;")
+                .bind(limit.window_reset_day(utils::get_epoch_day()))
                 .bind(limit.id)
                 .execute(db)
                 .await.unwrap();
         }
     }
+
+    pub async fn apply_pending(db: &Pool<Postgres>) {
+        // run once per day, before increment_limits
+        sqlx::query("delete from plutus.limit where pending_delete and pending_effective <= $1;")
+            .bind(utils::get_epoch_day())
+            .execute(db)
+            .await.unwrap();
+
+        sqlx::query("
+            update plutus.limit set
+                cap = coalesce(pending_cap, cap),
+                duration = coalesce(pending_duration, duration),
+                window_hours = coalesce(pending_window_hours, window_hours),
+                pending_cap = null, pending_duration = null, pending_window_hours = null, pending_effective = null
+            where pending_effective <= $1;
+        ")
+            .bind(utils::get_epoch_day())
+            .execute(db)
+            .await.unwrap();
+    }
     //
 
+    // whether the daily tasks would apply the pending change on `day` (epoch day)
+    pub fn is_pending_due(&self, day: i64) -> bool {
+        self.pending_effective.is_some_and(|d| d as i64 <= day)
+    }
+
+    // same as apply_pending, for a limit that isnt pending deletion
+    pub fn settle_pending(&mut self) {
+        self.cap = self.pending_cap.take().unwrap_or(self.cap);
+        self.duration = self.pending_duration.take().unwrap_or(self.duration);
+        self.window_hours = self.pending_window_hours.take().unwrap_or(self.window_hours);
+        self.pending_effective = None;
+    }
+
+    // start of the window `day` is in, windows keep their alignment even if the tasks didnt run for a while
+    pub fn window_reset_day(&self, day: i64) -> i32 {
+        let duration = self.duration.max(1) as i64;
+        (self.last_enforcement as i64 + (day - self.last_enforcement as i64).div_euclid(duration) * duration) as i32
+    }
+
+    // whether the daily tasks would reset this on `day` (epoch day)
+    pub fn is_due(&self, day: i64) -> bool {
+        !self.rolling && day - self.last_enforcement as i64 >= self.duration as i64
+    }
+
+    // successful transfers out of the limit's account(s) after `since` (epoch seconds), that the limit applies to
+    // as (epoch seconds, amount)
+    pub async fn spent_since(&self, db: &Pool<Postgres>, since: i64) -> Vec<(i64, f64)> {
+        sqlx::query("
+            select l.timestamp, l.balance
+            from plutus.log l
+            join plutus.account o on o.id = l.origin_account
+            left join plutus.account d on d.id = l.destination_account
+            where
+                (l.origin_account = $1 or ($1::bigint is null and o.owner = $8)) and
+                l.origin_kind = any($2) and
+                l.outcome = $3 and
+                l.timestamp > $4 and
+                ($5::bigint is null or l.destination_account = $5) and
+                ($6::text is null or d.owner = $6) and
+                (not $7 or d.owner is distinct from o.owner);
+        ")
+            .bind(self.account)
+            .bind([SourceSpecies::User, SourceSpecies::AutoTransfer, SourceSpecies::RoundUp, SourceSpecies::Rule].iter().flat_map(|s| s.keys()).copied().collect::<Vec<&str>>())
+            .bind(Outcome::Success.code())
+            .bind(since as f64)
+            .bind(self.counterparty_account)
+            .bind(self.counterparty_user.clone())
+            .bind(self.exempt_own)
+            .bind(self.owner.clone())
+            .fetch_all(db)
+            .await.unwrap()
+            .into_iter()
+            .map(|r| (r.get::<f64, usize>(0) as i64, r.get::<f64, usize>(1)))
+            .collect()
+    }
+
+    pub fn window_start(&self, now: i64) -> i64 {
+        now - self.window_hours as i64 * 3600
+    }
+
+    // rolling limits dont keep track of usage themselves
+    async fn with_usage(mut self, db: &Pool<Postgres>) -> Limit {
+        if self.rolling {
+            let sent = self.spent_since(db, self.window_start(utils::get_time())).await;
+            match self.species {
+                LimitSpecies::Cumulative => self.usage = sent.iter().map(|(_, a)| a).sum(),
+                LimitSpecies::Count => self.usage = sent.len() as f64,
+                LimitSpecies::Transaction => {}
+            }
+        }
+        self
+    }
+
     // account related
-    pub async fn check_limits(db: &Pool<Postgres>, account: i64, amount: f64) -> bool {
+    pub async fn check_limits(db: &Pool<Postgres>, origin: &Account, destination: &Account, amount: f64) -> Option<LimitError> {
         // check if using this amount surpasses any limits
+        Limit::check_all(&Limit::fetch_all(db, origin).await, origin, destination, amount)
+    }
+
+    // whether a transfer from `origin` to `destination` falls under this limit
+    pub fn applies_to(&self, origin: &Account, destination: &Account) -> bool {
+        (self.account == Some(origin.id) || self.owner.as_ref() == Some(&origin.owner)) &&
+            self.counterparty_account.is_none_or(|a| a == destination.id) &&
+            self.counterparty_user.as_ref().is_none_or(|u| *u == destination.owner) &&
+            !(self.exempt_own && destination.owner == origin.owner)
+    }
 
-        match Limit::fetch(db, account).await {
-            Some(l) => (l.usage + amount) > l.cap,
-            None => false
+    fn would_surpass(&self, amount: f64) -> bool {
+        match self.species {
+            LimitSpecies::Cumulative => (self.usage + amount) > self.cap,
+            LimitSpecies::Transaction => amount > self.cap,
+            LimitSpecies::Count => (self.usage + 1f64) > self.cap
         }
     }
-    // 
 
-    pub async fn create(db: &Pool<Postgres>, account: i64, cap: f64, duration: i32) -> Result<Limit, LimitError> {
-        // dont limit creation?
-        // multiple limits per account?
-        match Limit::fetch(db, account).await {
-            Some(_) => Err(LimitError::LimitAlreadyExists),
-            None => {
-                let mut result = Limit {
-                    id: 0,
-                    account,
-                    usage: 0f64,
-                    cap,
-                    duration,
-                    last_enforcement: utils::get_epoch_day() as i32
-                };
-
-                result.id = sqlx::query("insert into plutus.limit(account, usage, cap, duration, last_enforcement) values($1, $2, $3, $4, $5) returning id;")
-                    .bind(result.account)
-                    .bind(result.usage)
-                    .bind(result.cap)
-                    .bind(result.duration)
-                    .bind(result.last_enforcement)
-                    .fetch_one(db)
-                    .await.unwrap()
-                    .get(0);
-
-                Ok(result)
+    // out of the limits the transfer would surpass, the one with the least headroom
+    // amount based limits are reported before count based ones
+    pub fn check_all(limits: &[Limit], origin: &Account, destination: &Account, amount: f64) -> Option<LimitError> {
+        let surpassed = limits.iter()
+            .filter(|l| l.applies_to(origin, destination) && l.would_surpass(amount))
+            .collect::<Vec<&Limit>>();
+
+        match surpassed.iter().filter(|l| l.species != LimitSpecies::Count).min_by(|a, b| a.headroom().total_cmp(&b.headroom())) {
+            Some(l) => Some(LimitError::WillSurpassLimit { limit: l.id, headroom: l.headroom() }),
+            None => surpassed.iter()
+                .min_by(|a, b| a.headroom().total_cmp(&b.headroom()))
+                .map(|l| LimitError::WillSurpassVelocity { limit: l.id, transfers: l.headroom() as i64 })
+        }
+    }
+
+    // how much more can be sent (or how many more transfers can be made) before this limit is hit
+    pub fn headroom(&self) -> f64 {
+        match self.species {
+            LimitSpecies::Cumulative | LimitSpecies::Count => (self.cap - self.usage).max(0f64),
+            LimitSpecies::Transaction => self.cap
+        }
+    }
+
+    // notifies the owner about every threshold crossed since the last check
+    pub async fn check_thresholds(db: &Pool<Postgres>, origin: &Account, destination: &Account) {
+        for l in Limit::fetch_all(db, origin).await.into_iter().filter(|l| !l.thresholds.is_empty() && l.applies_to(origin, destination)) {
+            let percentage = if l.cap > 0f64 { l.usage / l.cap * 100f64 } else { 100f64 };
+
+            // rolling windows never reset as a whole, thresholds rearm as usage drops back under them
+            let mut alerted = l.alerted.iter().copied().filter(|t| !l.rolling || *t <= percentage).collect::<Vec<f64>>();
+            let crossed = l.thresholds.iter().copied().filter(|t| *t <= percentage && !alerted.contains(t)).collect::<Vec<f64>>();
+            if crossed.is_empty() && alerted.len() == l.alerted.len() {
+                continue;
+            }
+
+            if let Some(t) = crossed.iter().copied().max_by(|a, b| a.total_cmp(b)) {
+                let owner = l.owner.clone().unwrap_or(origin.owner.clone());
+                Notification::push(db, &owner, NotificationSpecies::LimitThreshold, l.id, format!("limit {} has reached {}% of its cap ({} of {})", l.id, t, l.usage, l.cap)).await;
             }
+            alerted.extend(crossed);
+
+            sqlx::query("update plutus.limit set alerted = $1 where id = $2;")
+                .bind(alerted)
+                .bind(l.id)
+                .execute(db)
+                .await.unwrap();
         }
     }
 
-    pub async fn fetch(db: &Pool<Postgres>, account: i64) -> Option<Limit> {
-        sqlx::query_as::<_, Limit>("select * from plutus.limit where account = $1;")
-            .bind(account)
-            .fetch_optional(db)
+    pub async fn increment_usage(db: &Pool<Postgres>, origin: &Account, destination: &Account, amount: f64) {
+        for l in Limit::fetch_all(db, origin).await.into_iter().filter(|l| !l.rolling && l.applies_to(origin, destination)) {
+            let increment = match l.species {
+                LimitSpecies::Cumulative => amount,
+                LimitSpecies::Count => 1f64,
+                LimitSpecies::Transaction => continue
+            };
+
+            sqlx::query("update plutus.limit set usage = usage + $1 where id = $2;")
+                .bind(increment)
+                .bind(l.id)
+                .execute(db)
+                .await.unwrap();
+        }
+    }
+    // 
+
+    pub async fn create(db: &Pool<Postgres>, mut candidate: Limit) -> Limit {
+        candidate.id = sqlx::query("
+            insert into plutus.limit(
+                account, owner, usage, cap, duration, last_enforcement, rolling, window_hours,
+                species, counterparty_account, counterparty_user, exempt_own, thresholds
+            ) values($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) returning id;
+        ")
+            .bind(candidate.account)
+            .bind(candidate.owner.clone())
+            .bind(candidate.usage)
+            .bind(candidate.cap)
+            .bind(candidate.duration)
+            .bind(candidate.last_enforcement)
+            .bind(candidate.rolling)
+            .bind(candidate.window_hours)
+            .bind(candidate.species.to_string())
+            .bind(candidate.counterparty_account)
+            .bind(candidate.counterparty_user.clone())
+            .bind(candidate.exempt_own)
+            .bind(candidate.thresholds.clone())
+            .fetch_one(db)
             .await.unwrap()
+            .get(0);
+
+        candidate.with_usage(db).await
+    }
+
+    pub async fn fetch(db: &Pool<Postgres>, id: i64) -> Option<Limit> {
+        match sqlx::query_as::<_, Limit>("select * from plutus.limit where id = $1;")
+            .bind(id)
+            .fetch_optional(db)
+            .await.unwrap() {
+            Some(l) => Some(l.with_usage(db).await),
+            None => None
+        }
     }
 
-    pub async fn delete(db: &Pool<Postgres>, account: i64) -> Option<LimitError> {
-        if Limit::fetch(db, account).await.is_none() {
+    // everything that applies to transfers out of `account`, including the owner's limits
+    pub async fn fetch_all(db: &Pool<Postgres>, account: &Account) -> Vec<Limit> {
+        let mut result = vec![];
+        for l in sqlx::query_as::<_, Limit>("select * from plutus.limit where account = $1 or owner = $2 order by id;")
+            .bind(account.id)
+            .bind(account.owner.clone())
+            .fetch_all(db)
+            .await.unwrap() {
+            result.push(l.with_usage(db).await);
+        }
+        result
+    }
+
+    // limits covering all of `user`'s accounts
+    pub async fn fetch_user(db: &Pool<Postgres>, user: String) -> Vec<Limit> {
+        let mut result = vec![];
+        for l in sqlx::query_as::<_, Limit>("select * from plutus.limit where owner = $1 order by id;")
+            .bind(user)
+            .fetch_all(db)
+            .await.unwrap() {
+            result.push(l.with_usage(db).await);
+        }
+        result
+    }
+
+    // only scheduled, see cooling_off_days
+    pub async fn delete(db: &Pool<Postgres>, id: i64) -> Option<LimitError> {
+        if Limit::fetch(db, id).await.is_none() {
             return Some(LimitError::LimitDoesntExist);
         }
 
-        sqlx::query("delete from plutus.limit where account = $1;")
-            .bind(account)
+        sqlx::query("
+            update plutus.limit set
+                pending_cap = null, pending_duration = null, pending_window_hours = null,
+                pending_delete = true, pending_effective = $1
+            where id = $2;
+        ")
+            .bind((utils::get_epoch_day() + cooling_off_days()) as i32)
+            .bind(id)
             .execute(db)
             .await.unwrap();
 
         None
     }
 
-    pub async fn edit(db: &Pool<Postgres>, account: i64, cap: f64, duration: i32) -> Option<LimitError> {
-        if Limit::fetch(db, account).await.is_none() {
-            return Some(LimitError::LimitDoesntExist);
+    // field by field, tightening applies right away, loosening (higher cap, shorter window) only after the cooling off period
+    // either way any earlier pending change is replaced
+    pub async fn edit(db: &Pool<Postgres>, id: i64, cap: f64, duration: i32, window_hours: Option<i32>) -> Option<LimitError> {
+        let l = match Limit::fetch(db, id).await {
+            Some(l) => l,
+            None => return Some(LimitError::LimitDoesntExist)
+        };
+        let window_hours = window_hours.unwrap_or(l.window_hours);
+
+        let cap_pending = cap > l.cap;
+        let duration_pending = !l.rolling && duration < l.duration;
+        let window_pending = l.rolling && window_hours < l.window_hours;
+        let pending = cap_pending || duration_pending || window_pending;
+
+        sqlx::query("
+            update plutus.limit set
+                cap = $1, duration = $2, window_hours = $3,
+                pending_cap = $4, pending_duration = $5, pending_window_hours = $6,
+                pending_delete = false, pending_effective = $7
+            where id = $8;
+        ")
+            .bind(if cap_pending { l.cap } else { cap })
+            .bind(if duration_pending { l.duration } else { duration })
+            .bind(if window_pending { l.window_hours } else { window_hours })
+            .bind(cap_pending.then_some(cap))
+            .bind(duration_pending.then_some(duration))
+            .bind(window_pending.then_some(window_hours))
+            .bind(pending.then(|| (utils::get_epoch_day() + cooling_off_days()) as i32))
+            .bind(id)
+            .execute(db)
+            .await.unwrap();
+
+        None
+    }
+
+    // applies right away, alerts already sent this window stay sent
+    pub async fn set_thresholds(db: &Pool<Postgres>, id: i64, thresholds: Vec<f64>) {
+        sqlx::query("update plutus.limit set thresholds = $1 where id = $2;")
+            .bind(thresholds)
+            .bind(id)
+            .execute(db)
+            .await.unwrap();
+    }
+
+    pub async fn cancel_pending(db: &Pool<Postgres>, id: i64) -> Option<LimitError> {
+        match Limit::fetch(db, id).await {
+            Some(l) if l.pending_effective.is_some() => {},
+            Some(_) => return Some(LimitError::NoPendingChange),
+            None => return Some(LimitError::LimitDoesntExist)
         }
 
-        sqlx::query("update plutus.limit set cap = $1, duration = $2;")
-            .bind(cap)
-            .bind(duration)
+        sqlx::query("
+            update plutus.limit set
+                pending_cap = null, pending_duration = null, pending_window_hours = null,
+                pending_delete = false, pending_effective = null
+            where id = $1;
+        ")
+            .bind(id)
             .execute(db)
             .await.unwrap();
 
@@ -115,13 +411,37 @@ impl Limit {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+// errors if the session's user doesnt own "limit" (or the account its on)
+async fn owned_limit(db: &Pool<Postgres>, query: &HashMap<String, String>, user: String) -> Result<i64, Outcome> {
+    let id = utils::from_query("limit", query).parse::<i64>().unwrap();
+
+    match Limit::fetch(db, id).await {
+        Some(l) => match (l.account, l.owner) {
+            (_, Some(o)) if o == user => Ok(id),
+            (Some(a), _) if Account::is_owner(db, a, user).await => Ok(id),
+            _ => Err(Outcome::Account(AccountError::NoPermission))
+        },
+        None => Err(Outcome::Account(AccountError::NoPermission))
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
 pub enum LimitError {
-    LimitAlreadyExists,
     LimitDoesntExist,
 
     SurpassedLimit,
-    WillSurpassLimit
+    WillSurpassLimit { limit: i64, headroom: f64 }, // headroom being how much can still be sent
+    WillSurpassVelocity { limit: i64, transfers: i64 }, // transfers being how many are left this window
+
+    NoPendingChange,
+}
+
+// "50,80,100" -> percentages of cap
+fn parse_thresholds(raw: &str) -> Option<Vec<f64>> {
+    let mut result = raw.split(',').map(|t| t.trim().parse::<f64>().ok().filter(|t| t.is_finite() && *t > 0f64)).collect::<Option<Vec<f64>>>()?;
+    result.sort_by(|a, b| a.total_cmp(b));
+    result.dedup();
+    Some(result)
 }
 
 pub async fn create(
@@ -130,26 +450,74 @@ pub async fn create(
     WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
 ) -> impl IntoResponse {
     utils::request_boiler(app_state, query, session_id, vec![
-        ("account", PlutusFormat::BigNumber),
         ("cap", PlutusFormat::Float),
         ("duration", PlutusFormat::Number)
     ], |db, session, query| async move {
-        let id = utils::from_query("account", &query).parse::<i64>().unwrap();
+        // optional args
+        // "account" : the account this limit is on, otherwise it covers all of the user's accounts
+        // "window_hours" : makes this a rolling limit, "duration" is ignored then
+        // "species" : Cumulative (default), Transaction, Count
+        // "counterparty_account" : only transfers to this account count
+        // "counterparty_user" : only transfers to this user's accounts count
+        // "exempt_own" : true -> transfers between the user's own accounts dont count, the default for user wide amount limits
+        // "thresholds" : comma separated percentages of cap to get notified at, eg. 50,80,100
+        let (account, owner) = match utils::optional_query::<i64>("account", &query) {
+            Ok(Some(_)) => match log::owned_account(&db, &query, session.user).await {
+                Ok(i) => (Some(i), None),
+                Err(e) => return e
+            },
+            Ok(None) => (None, Some(session.user)),
+            Err(e) => return Outcome::Plutus(e)
+        };
 
-        if !Account::is_owner(&db, id, session.user).await {
-            return Outcome::Account(AccountError::NoPermission);
-        }
+        let window_hours = match utils::optional_query::<i32>("window_hours", &query) {
+            Ok(w) if w.unwrap_or(1) > 0 => w,
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
 
-        match Limit::create(
-            &db,
-            id,
-            utils::from_query("cap", &query).parse::<f64>().unwrap(),
-            utils::from_query("duration", &query).parse::<i32>().unwrap()
-        ).await
-        .map_err(|e| Outcome::Limit(e)) {
-            Ok(l) => Outcome::Data(serde_json::to_string(&l).unwrap()),
-            Err(e) => e
+        let mut candidate = match (
+            utils::optional_query::<LimitSpecies>("species", &query),
+            utils::optional_query::<i64>("counterparty_account", &query),
+            utils::optional_query::<String>("counterparty_user", &query),
+            utils::optional_query::<bool>("exempt_own", &query)
+        ) {
+            (Ok(species), Ok(counterparty_account), Ok(counterparty_user), Ok(exempt_own)) => Limit {
+                id: 0,
+                account,
+                usage: 0f64,
+                cap: utils::from_query("cap", &query).parse::<f64>().unwrap(),
+                duration: utils::from_query("duration", &query).parse::<i32>().unwrap(),
+                last_enforcement: utils::get_epoch_day() as i32,
+                rolling: window_hours.is_some(),
+                window_hours: window_hours.unwrap_or(0),
+                species: species.unwrap_or(LimitSpecies::Cumulative),
+                counterparty_account,
+                counterparty_user,
+                // user wide amount limits are about money leaving the user, not shuffling it between their accounts
+                // count limits are about fraud, where every transfer counts
+                exempt_own: exempt_own.unwrap_or(owner.is_some() && species.is_none_or(|s| s != LimitSpecies::Count)),
+                owner,
+                pending_cap: None,
+                pending_duration: None,
+                pending_window_hours: None,
+                pending_delete: false,
+                pending_effective: None,
+                thresholds: vec![],
+                alerted: vec![]
+            },
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        if let Some(raw) = query.get("thresholds") {
+            match parse_thresholds(raw) {
+                Some(t) => candidate.thresholds = t,
+                None => return Outcome::Plutus(PlutusError::InvalidFormat)
+            }
         }
+
+        let l = Limit::create(&db, candidate).await;
+
+        Outcome::Data(serde_json::to_string(&l).unwrap())
     }).await
 }
 
@@ -160,15 +528,28 @@ pub async fn fetch(
 ) -> impl IntoResponse {
     utils::request_boiler(app_state, query, session_id, vec![
         ("account", PlutusFormat::BigNumber)
-    ], |db, _, query| async move {
-        Outcome::Data(
-            serde_json::to_string(
-                &Limit::fetch(
-                    &db,
-                    utils::from_query("account", &query).parse::<i64>().unwrap()
-                ).await
-            ).unwrap()
-        )
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        let account = match Account::fetch(&db, id).await {
+            Some(a) => a,
+            None => return Outcome::Account(AccountError::NoExist)
+        };
+
+        Outcome::Data(serde_json::to_string(&Limit::fetch_all(&db, &account).await).unwrap())
+    }).await
+}
+
+pub async fn fetch_user(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![], |db, session, _| async move {
+        Outcome::Data(serde_json::to_string(&Limit::fetch_user(&db, session.user).await).unwrap())
     }).await
 }
 
@@ -178,13 +559,12 @@ pub async fn delete(
     WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
 ) -> impl IntoResponse {
     utils::request_boiler(app_state, query, session_id, vec![
-        ("account", PlutusFormat::BigNumber)
+        ("limit", PlutusFormat::BigNumber)
     ], |db, session, query| async move {
-        let id = utils::from_query("account", &query).parse::<i64>().unwrap();
-
-        if !Account::is_owner(&db, id, session.user).await {
-            return Outcome::Account(AccountError::NoPermission);
-        }
+        let id = match owned_limit(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
 
         match Limit::delete(&db, id).await {
             Some(e) => Outcome::Limit(e),
@@ -199,22 +579,69 @@ pub async fn edit(
     WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
 ) -> impl IntoResponse {
     utils::request_boiler(app_state, query, session_id, vec![
-        ("account", PlutusFormat::BigNumber),
+        ("limit", PlutusFormat::BigNumber),
         ("cap", PlutusFormat::Float),
         ("duration", PlutusFormat::Number)
     ], |db, session, query| async move {
-        let id = utils::from_query("account", &query).parse::<i64>().unwrap();
+        // optional args
+        // "window_hours" : only for rolling limits
+        // "thresholds" : comma separated percentages of cap to get notified at, empty to turn them off
+        let id = match owned_limit(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
 
-        if !Account::is_owner(&db, id, session.user).await {
-            return Outcome::Account(AccountError::NoPermission);
+        let thresholds = match query.get("thresholds").map(|raw| raw.as_str()) {
+            None => None,
+            Some("") => Some(vec![]),
+            Some(raw) => match parse_thresholds(raw) {
+                Some(t) => Some(t),
+                None => return Outcome::Plutus(PlutusError::InvalidFormat)
+            }
+        };
+
+        let window_hours = match utils::optional_query::<i32>("window_hours", &query) {
+            Ok(w) if w.unwrap_or(1) > 0 => w,
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        // non-rolling limits have no window to change
+        if window_hours.is_some() && Limit::fetch(&db, id).await.is_some_and(|l| !l.rolling) {
+            return Outcome::Plutus(PlutusError::InvalidArguments);
         }
 
         match Limit::edit(
             &db,
             id,
             utils::from_query("cap", &query).parse::<f64>().unwrap(),
-            utils::from_query("duration", &query).parse::<i32>().unwrap()
+            utils::from_query("duration", &query).parse::<i32>().unwrap(),
+            window_hours
         ).await {
+            Some(e) => Outcome::Limit(e),
+            None => {
+                if let Some(t) = thresholds {
+                    Limit::set_thresholds(&db, id, t).await;
+                }
+                Outcome::Success
+            }
+        }
+    }).await
+}
+
+pub async fn cancel(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("limit", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match owned_limit(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        match Limit::cancel_pending(&db, id).await {
             Some(e) => Outcome::Limit(e),
             None => Outcome::Success
         }
@@ -3,28 +3,46 @@ use std::collections::HashMap;
 use axum::{extract::{Query, State}, response::IntoResponse, Json};
 use axum_extra::extract::WithRejection;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, Pool, Postgres};
+use sqlx::{prelude::FromRow, types::JsonValue, Executor, PgConnection, Pool, Postgres, QueryBuilder, Row};
+use strum_macros::EnumString;
 
-use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, plutus_error::{Outcome, PlutusFormat}, session::RawSessionID, utils, AppState};
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
 
-#[derive(FromRow, Serialize, Deserialize, Clone)]
+#[derive(FromRow, Serialize, Deserialize)]
 pub struct RawLog {
     pub id: i64,
     pub balance: f64,
-    pub origin: String,
-    pub destination: String,
-    pub state: String,
-    pub timestamp: f64
+    pub origin_kind: String,
+    pub origin_account: Option<i64>,
+    pub origin_description: Option<String>,
+    pub destination_kind: String,
+    pub destination_account: Option<i64>,
+    pub destination_description: Option<String>,
+    pub outcome: String,
+    pub state: JsonValue,
+    pub timestamp: f64,
+    pub origin_balance: Option<f64>,
+    pub destination_balance: Option<f64>,
+    pub auto_transfer: Option<i64>,
+    pub catch_up: Option<i32>,
+    pub shortfall: Option<f64>,
+    pub posts: Option<i64>
 }
-impl Into<Log> for RawLog {
-    fn into(self) -> Log {
+impl From<RawLog> for Log {
+    fn from(r: RawLog) -> Log {
         Log {
-            id: self.id,
-            balance: self.balance,
-            origin: serde_json::from_str(&self.origin).unwrap(),
-            destination: serde_json::from_str(&self.destination).unwrap(),
-            state: serde_json::from_str(&self.state).unwrap(),
-            timestamp: self.timestamp
+            id: r.id,
+            balance: r.balance,
+            origin: Source::from_columns(&r.origin_kind, r.origin_account, r.origin_description),
+            destination: Source::from_columns(&r.destination_kind, r.destination_account, r.destination_description),
+            state: serde_json::from_value(r.state).unwrap(),
+            timestamp: r.timestamp,
+            origin_balance: r.origin_balance,
+            destination_balance: r.destination_balance,
+            auto_transfer: r.auto_transfer,
+            catch_up: r.catch_up,
+            shortfall: r.shortfall,
+            posts: r.posts
         }
     }
 }
@@ -36,57 +54,408 @@ pub struct Log {
     pub origin: Source, // from who
     pub destination: Source, // to who
     pub state: Outcome, // whether successful or not
-    pub timestamp: f64
+    pub timestamp: f64,
+    pub origin_balance: Option<f64>, // balance of origin right after this log
+    pub destination_balance: Option<f64>, // balance of destination right after this log
+    pub auto_transfer: Option<i64>, // the auto transfer that made this, if any
+    pub catch_up: Option<i32>, // epoch day of the missed run this made up for
+    pub shortfall: Option<f64>, // what a partial run couldnt send
+    pub posts: Option<i64> // the imported log this posted onto the balance, if any
+}
+
+// which run of an auto transfer a log came from
+#[derive(Clone, Copy)]
+pub struct RunLink {
+    pub auto_transfer: i64,
+    pub catch_up: Option<i32>, // epoch day of the missed run being made up for
+    pub shortfall: Option<f64>, // what was left unsent when only part of the amount was available
 }
 impl Log {
-    pub async fn append(db: &Pool<Postgres>, balance: f64, origin: Source, destination: Source, state: Outcome) {
-        sqlx::query("insert into plutus.log(balance, origin, destination, state, timestamp) values($1, $2, $3, $4, $5);")
-            // .bind(serde_json::to_string(&species).unwrap())
-            .bind(balance)
-            .bind(serde_json::to_string(&origin).unwrap())
-            .bind(serde_json::to_string(&destination).unwrap())
-            .bind(serde_json::to_string(&state).unwrap())
-            .bind(utils::get_time())
-            .execute(db)
+    // for failed runs of an auto transfer
+    pub async fn append_auto_transfer(db: &Pool<Postgres>, balance: f64, origin: Source, destination: Source, state: Outcome, run: RunLink) {
+        Log::insert(db, balance, (origin, destination), state, utils::get_time(), (None, None), Some(run)).await;
+    }
+
+    // for successful transfers, along with the balances of both sides after it went through
+    pub async fn append_with_balance(db: &Pool<Postgres>, balance: f64, origin: Source, destination: Source, balances: (f64, f64), run: Option<RunLink>) {
+        Log::insert(db, balance, (origin, destination), Outcome::Success, utils::get_time(), (Some(balances.0), Some(balances.1)), run).await;
+    }
+
+    // for entries that didnt happen just now (eg: imported ones), can be part of a transaction
+    pub async fn append_at<'c, E: Executor<'c, Database = Postgres>>(db: E, balance: f64, origin: Source, destination: Source, state: Outcome, timestamp: i64) {
+        Log::insert(db, balance, (origin, destination), state, timestamp, (None, None), None).await;
+    }
+
+    // for posting the imported log `posts`, as part of the transaction changing the balance
+    pub async fn append_posted(tx: &mut PgConnection, balance: f64, origin: Source, destination: Source, balances: (Option<f64>, Option<f64>), posts: i64) {
+        let id = Log::insert(&mut *tx, balance, (origin, destination), Outcome::Success, utils::get_time(), balances, None).await;
+        sqlx::query("update plutus.log set posts = $1 where id = $2;")
+            .bind(posts)
+            .bind(id)
+            .execute(&mut *tx)
             .await.unwrap();
     }
 
-    pub async fn fetch(db: &Pool<Postgres>, account: i64, amount: i32) -> Vec<Log> {
-        // not sure why the regular method doesnt work
-        // possible sql injection vulnerability?
-        sqlx::query_as::<_, RawLog>(&format!("
+    // returns the id of the new log
+    async fn insert<'c, E: Executor<'c, Database = Postgres>>(db: E, balance: f64, (origin, destination): (Source, Source), state: Outcome, timestamp: i64, balances: (Option<f64>, Option<f64>), run: Option<RunLink>) -> i64 {
+        sqlx::query("
+            insert into plutus.log(
+                balance,
+                origin_kind, origin_account, origin_description,
+                destination_kind, destination_account, destination_description,
+                outcome, state, timestamp,
+                origin_balance, destination_balance, auto_transfer, catch_up, shortfall
+            ) values($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) returning id;
+        ")
+            .bind(balance)
+            .bind(origin.kind())
+            .bind(origin.account())
+            .bind(origin.description())
+            .bind(destination.kind())
+            .bind(destination.account())
+            .bind(destination.description())
+            .bind(state.code())
+            .bind(serde_json::to_value(&state).unwrap())
+            .bind(timestamp as f64)
+            .bind(balances.0)
+            .bind(balances.1)
+            .bind(run.map(|r| r.auto_transfer))
+            .bind(run.and_then(|r| r.catch_up))
+            .bind(run.and_then(|r| r.shortfall))
+            .fetch_one(db)
+            .await.unwrap()
+            .get::<i64, usize>(0)
+    }
+
+    pub async fn fetch_by_id(db: &Pool<Postgres>, id: i64) -> Option<Log> {
+        sqlx::query_as::<_, RawLog>("select * from plutus.log where id = $1;")
+            .bind(id)
+            .fetch_optional(db)
+            .await.unwrap()
+            .map(|x| x.into())
+    }
+
+    // newest first
+    pub async fn fetch(db: &Pool<Postgres>, account: i64, amount: i32, filter: &LogFilter) -> Vec<Log> {
+        let mut query = QueryBuilder::<Postgres>::new("select * from plutus.log where ");
+        match filter.direction {
+            None => query.push("(origin_account = ").push_bind(account).push(" or destination_account = ").push_bind(account).push(")"),
+            Some(LogSpecies::Incoming) => query.push("destination_account = ").push_bind(account),
+            Some(LogSpecies::Outgoing) => query.push("origin_account = ").push_bind(account)
+        };
+
+        if let Some(from) = filter.from {
+            query.push(" and timestamp >= ").push_bind(from as f64);
+        }
+        if let Some(to) = filter.to {
+            query.push(" and timestamp <= ").push_bind(to as f64);
+        }
+
+        if let Some(t) = filter.auto_transfer {
+            query.push(" and auto_transfer = ").push_bind(t);
+        }
+
+        if let Some(c) = filter.counterparty {
+            query.push(" and ((origin_account = ").push_bind(account).push(" and destination_account = ").push_bind(c)
+                .push(") or (origin_account = ").push_bind(c).push(" and destination_account = ").push_bind(account)
+                .push("))");
+        }
+
+        if let Some(min) = filter.min {
+            query.push(" and balance >= ").push_bind(min);
+        }
+        if let Some(max) = filter.max {
+            query.push(" and balance <= ").push_bind(max);
+        }
+
+        if let Some(species) = &filter.source {
+            query.push(" and (origin_kind = any(").push_bind(species.keys())
+                .push(") or destination_kind = any(").push_bind(species.keys())
+                .push("))");
+        }
+
+        match filter.outcome {
+            None => {},
+            Some(OutcomeSpecies::Success) => { query.push(" and outcome = ").push_bind(Outcome::Success.code()); },
+            Some(OutcomeSpecies::Failure) => { query.push(" and outcome <> ").push_bind(Outcome::Success.code()); }
+        }
+
+        if let Some((timestamp, id)) = filter.cursor {
+            query.push(" and (timestamp < ").push_bind(timestamp as f64)
+                .push(" or (timestamp = ").push_bind(timestamp as f64)
+                .push(" and id < ").push_bind(id)
+                .push("))");
+        }
+
+        query.push(" order by timestamp desc, id desc limit ").push_bind(amount);
+
+        query.build_query_as::<RawLog>()
+            .fetch_all(db)
+            .await.unwrap()
+            .into_iter().map(|x| x.into())
+            .collect::<Vec<Log>>()
+    }
+
+    // oldest first, both ends inclusive (epoch seconds)
+    pub async fn fetch_range(db: &Pool<Postgres>, account: i64, from: i64, to: i64) -> Vec<Log> {
+        sqlx::query_as::<_, RawLog>("
         select *
         from plutus.log
         where
-            (origin::jsonb ->> 'AutoTransfer' = '{account}') or
-            (origin::jsonb ->> 'User' = '{account}') or
-            (origin::jsonb ->> 'Bank' = '{account}') or
-
-            (destination::jsonb ->> 'AutoTransfer' = '{account}') or
-            (destination::jsonb ->> 'User' = '{account}') or
-            (destination::jsonb ->> 'Bank' = '{account}')
-            
-            order by timestamp desc limit $1;
-        ").to_string())
-            .bind(amount)
+            (origin_account = $1 or destination_account = $1) and
+            timestamp >= $2 and timestamp <= $3
+
+            order by timestamp asc, id asc;
+        ")
+            .bind(account)
+            .bind(from as f64)
+            .bind(to as f64)
             .fetch_all(db)
             .await.unwrap()
-            .iter().map(|x| RawLog::into(x.clone()))
+            .into_iter().map(|x| x.into())
             .collect::<Vec<Log>>()
     }
+
+    // logs of `account` made after the log `last_log`, up to `to`
+    pub async fn fetch_after(db: &Pool<Postgres>, account: i64, last_log: i64, to: i64) -> Vec<Log> {
+        sqlx::query_as::<_, RawLog>("
+        select *
+        from plutus.log
+        where
+            (origin_account = $1 or destination_account = $1) and
+            id > $2 and timestamp <= $3
+
+            order by timestamp asc, id asc;
+        ")
+            .bind(account)
+            .bind(last_log)
+            .bind(to as f64)
+            .fetch_all(db)
+            .await.unwrap()
+            .into_iter().map(|x| x.into())
+            .collect::<Vec<Log>>()
+    }
+
+    // balance of `account` right after this log, if it was recorded
+    pub fn resulting_balance(&self, account: i64) -> Option<f64> {
+        if self.destination.account() == Some(account) {
+            self.destination_balance
+        } else if self.origin.account() == Some(account) {
+            self.origin_balance
+        } else {
+            None
+        }
+    }
+
+    // failed transfers and unposted imports never touched any balance
+    pub fn affects_balance(&self) -> bool {
+        self.state == Outcome::Success &&
+            !matches!(self.origin, Source::Import(_)) &&
+            !matches!(self.destination, Source::Import(_))
+    }
+
+    // how much this log moved the balance of `account` by
+    pub fn signed_amount(&self, account: i64) -> f64 {
+        if !self.affects_balance() {
+            return 0f64;
+        }
+
+        let mut result = 0f64;
+        if self.destination.account() == Some(account) {
+            result += self.balance;
+        }
+        if self.origin.account() == Some(account) {
+            result -= self.balance;
+        }
+        result
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+// optional args for /log/fetch, everything left out isnt filtered on
+#[derive(Default)]
+pub struct LogFilter {
+    pub from: Option<i64>, // epoch seconds, inclusive
+    pub to: Option<i64>, // epoch seconds, inclusive
+    pub direction: Option<LogSpecies>,
+    pub counterparty: Option<i64>, // account on the other side
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub source: Option<SourceSpecies>,
+    pub outcome: Option<OutcomeSpecies>,
+    pub auto_transfer: Option<i64>,
+    pub cursor: Option<(i64, i64)>, // (timestamp, id) of the last log of the previous page
+}
+impl LogFilter {
+    pub fn from_query(q: &HashMap<String, String>) -> Result<LogFilter, PlutusError> {
+        Ok(LogFilter {
+            from: utils::optional_query("from", q)?,
+            to: utils::optional_query("to", q)?,
+            direction: utils::optional_query("direction", q)?,
+            counterparty: utils::optional_query("counterparty", q)?,
+            min: utils::optional_query("min", q)?,
+            max: utils::optional_query("max", q)?,
+            source: utils::optional_query("source", q)?,
+            outcome: utils::optional_query("outcome", q)?,
+            auto_transfer: utils::optional_query("auto_transfer", q)?,
+            cursor: match q.get("cursor") {
+                Some(c) => Some(decode_cursor(c).ok_or(PlutusError::InvalidFormat)?),
+                None => None
+            }
+        })
+    }
+}
+
+// cursors are opaque to clients, just hand back whatever was given
+fn encode_cursor(log: &Log) -> String {
+    format!("{:x}-{:x}", log.timestamp as i64, log.id)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(i64, i64)> {
+    let (timestamp, id) = cursor.split_once('-')?;
+    Some((i64::from_str_radix(timestamp, 16).ok()?, i64::from_str_radix(id, 16).ok()?))
+}
+
+#[derive(Serialize)]
+pub struct LogPage {
+    pub logs: Vec<AccountLog>,
+    pub cursor: Option<String>, // none when there is nothing left
+}
+impl LogPage {
+    pub async fn fetch(db: &Pool<Postgres>, account: i64, amount: i32, filter: &LogFilter) -> LogPage {
+        // one extra to know if theres another page
+        let mut logs = Log::fetch(db, account, amount + 1, filter).await;
+        let cursor = if logs.len() > amount as usize {
+            logs.truncate(amount as usize);
+            logs.last().map(encode_cursor)
+        } else {
+            None
+        };
+
+        LogPage {
+            logs: logs.into_iter().map(|l| AccountLog::new(l, account)).collect(),
+            cursor
+        }
+    }
+}
+
+// a log as seen from one of the accounts involved
+#[derive(Serialize)]
+pub struct AccountLog {
+    #[serde(flatten)]
+    pub log: Log,
+    pub resulting_balance: Option<f64>
+}
+impl AccountLog {
+    pub fn new(log: Log, account: i64) -> AccountLog {
+        AccountLog {
+            resulting_balance: log.resulting_balance(account),
+            log
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, EnumString)]
 pub enum LogSpecies {
     Incoming,
     Outgoing
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, EnumString)]
+pub enum SourceSpecies {
+    Bank,
+    User,
+    AutoTransfer,
+    RoundUp,
+    Rule,
+    Import
+}
+impl SourceSpecies {
+    // Source variants that fall under this species
+    pub fn keys(&self) -> &'static [&'static str] {
+        match self {
+            SourceSpecies::Bank => &["Bank"],
+            SourceSpecies::User => &["User"],
+            SourceSpecies::AutoTransfer => &["AutoTransfer"],
+            SourceSpecies::RoundUp => &["RoundUp"],
+            SourceSpecies::Rule => &["Rule"],
+            SourceSpecies::Import => &["Import", "ImportPosted", "External"]
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, EnumString)]
+pub enum OutcomeSpecies {
+    Success,
+    Failure
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum Source {
     Bank,
     User(i64), // from
     AutoTransfer(i64), // from (account_id)
+    RoundUp(i64), // account_id, the difference moved into savings
+    Rule(i64), // account_id, passed on by a transfer rule
+
+    // imported from outside of plutus (eg: csv)
+    Import(i64), // account_id, not reflected in the balance yet
+    ImportPosted(i64), // account_id, reflected in the balance
+    External(String), // the other side of an import (description)
+}
+impl Source {
+    pub fn account(&self) -> Option<i64> {
+        match self {
+            Source::Bank | Source::External(_) => None,
+            Source::User(a) | Source::AutoTransfer(a) | Source::RoundUp(a) | Source::Rule(a) | Source::Import(a) | Source::ImportPosted(a) => Some(*a)
+        }
+    }
+
+    pub fn description(&self) -> Option<String> {
+        match self {
+            Source::External(d) => Some(d.clone()),
+            _ => None
+        }
+    }
+
+    // stored in the *_kind columns
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Source::Bank => "Bank",
+            Source::User(_) => "User",
+            Source::AutoTransfer(_) => "AutoTransfer",
+            Source::RoundUp(_) => "RoundUp",
+            Source::Rule(_) => "Rule",
+            Source::Import(_) => "Import",
+            Source::ImportPosted(_) => "ImportPosted",
+            Source::External(_) => "External"
+        }
+    }
+
+    pub fn from_columns(kind: &str, account: Option<i64>, description: Option<String>) -> Source {
+        // constrained by the schema, anything else means the db is corrupt
+        match kind {
+            "Bank" => Source::Bank,
+            "User" => Source::User(account.unwrap()),
+            "AutoTransfer" => Source::AutoTransfer(account.unwrap()),
+            "RoundUp" => Source::RoundUp(account.unwrap()),
+            "Rule" => Source::Rule(account.unwrap()),
+            "Import" => Source::Import(account.unwrap()),
+            "ImportPosted" => Source::ImportPosted(account.unwrap()),
+            "External" => Source::External(description.unwrap_or_default()),
+            _ => panic!("unknown source kind {kind}")
+        }
+    }
+}
+
+// errors if the session's user doesnt own "account"
+pub async fn owned_account(db: &Pool<Postgres>, query: &HashMap<String, String>, user: String) -> Result<i64, Outcome> {
+    let id = utils::from_query("account", query).parse::<i64>().unwrap();
+
+    if !Account::is_owner(db, id, user).await {
+        return Err(Outcome::Account(AccountError::NoExist));
+    }
+
+    Ok(id)
 }
 
 
@@ -99,16 +468,27 @@ pub async fn fetch(
         ("account", PlutusFormat::BigNumber),
         ("amount", PlutusFormat::Number)
     ], |db, s, q| async move {
-        let id = utils::from_query("account", &q).parse::<i64>().unwrap();
+        // optional args
+        // "from", "to" : epoch seconds
+        // "direction" : Incoming, Outgoing
+        // "counterparty" : account id
+        // "min", "max" : amount
+        // "source" : Bank, User, AutoTransfer, RoundUp, Rule, Import
+        // "outcome" : Success, Failure
+        // "auto_transfer" : only runs of this auto transfer
+        // "cursor" : from the previous page
+        let id = match owned_account(&db, &q, s.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
 
-        let amount = utils::from_query("amount", &q).parse::<i32>().unwrap().min(100);
+        let amount = utils::from_query("amount", &q).parse::<i32>().unwrap().clamp(1, 100);
 
-        if !Account::is_owner(&db, id, s.user).await {
-            return Outcome::Account(AccountError::NoExist);
-        }
+        let filter = match LogFilter::from_query(&q) {
+            Ok(f) => f,
+            Err(e) => return Outcome::Plutus(e)
+        };
 
-        Outcome::Data(
-            serde_json::to_string(&Log::fetch(&db, id, amount).await).unwrap()
-        )
+        Outcome::Data(serde_json::to_string(&LogPage::fetch(&db, id, amount, &filter).await).unwrap())
     }).await
 }
@@ -15,6 +15,15 @@ mod account;
 mod limit;
 mod auto_transfer;
 mod log;
+mod export;
+mod import;
+mod analytics;
+mod forecast;
+mod snapshot;
+mod notification;
+mod schedule;
+mod round_up;
+mod transfer_rule;
 
 pub async fn not_implemented_yet() -> Response {
     (StatusCode::NOT_IMPLEMENTED, "not implemented yet chill".to_string()).into_response()
@@ -25,6 +34,13 @@ pub struct AppState {
     pub db: Pool<Postgres>
 }
 
+// epoch day the daily tasks last ran on
+pub async fn last_incremented(db: &Pool<Postgres>) -> i64 {
+    sqlx::query("select coalesce(max(last_incremented), 0) from plutus.scheduling;")
+        .fetch_one(db)
+        .await.unwrap().get::<i64, usize>(0)
+}
+
 pub async fn increment_tasks(db: &Pool<Postgres>) {
     let mut interval = tokio::time::interval(Duration::from_secs(60 * 20)); // 20 min interval
     loop {
@@ -32,9 +48,7 @@ pub async fn increment_tasks(db: &Pool<Postgres>) {
 
         let today = utils::get_epoch_day();
 
-        let last_incremented = sqlx::query("select coalesce(max(last_incremented), 0) from plutus.scheduling;")
-            .fetch_one(db)
-            .await.unwrap().get::<i64, usize>(0);
+        let last_incremented = last_incremented(db).await;
 
         if today != last_incremented {
             sqlx::query("insert into plutus.scheduling(last_incremented) values($1);")
@@ -42,8 +56,11 @@ pub async fn increment_tasks(db: &Pool<Postgres>) {
                 .execute(db)
                 .await.unwrap();
 
+            snapshot::Snapshot::record_all(db).await;
+            limit::Limit::apply_pending(db).await;
             limit::Limit::increment_limits(db).await;
             auto_transfer::AutoTransfer::increment_auto_transfers(db).await;
+            round_up::RoundUp::sweep_all(db).await;
         }
 
         // wait every 20 mins
@@ -61,6 +78,8 @@ async fn main() {
         db: PgPool::connect(env::var("PG_ADDRESS").unwrap().to_string().replace("[YOUR-PASSWORD]", env::var("PG_PASSWORD").unwrap().as_str()).to_string().as_str()).await.unwrap()
     };
 
+    sqlx::migrate!().run(&app_state.db).await.unwrap();
+
     let db_clone = app_state.db.clone();
     tokio::spawn(async move {
         increment_tasks(&db_clone).await;
@@ -76,23 +95,44 @@ async fn main() {
         .route("/account/delete", post(account::delete))
         .route("/account/fetch", post(account::fetch))
         .route("/account/fetch/all", post(account::fetch_all))
+        .route("/account/balance/at", post(snapshot::at))
+        .route("/account/balance/series", post(snapshot::series))
 
         .route("/limit/create", post(limit::create))
         .route("/limit/fetch", post(limit::fetch))
+        .route("/limit/fetch/user", post(limit::fetch_user))
         .route("/limit/delete", post(limit::delete))
         .route("/limit/edit", post(limit::edit))
+        .route("/limit/cancel", post(limit::cancel))
+        .route("/notification/fetch", post(notification::fetch))
 
         .route("/auto_transfer/create", post(auto_transfer::create))
         .route("/auto_transfer/edit", post(auto_transfer::edit))
         .route("/auto_transfer/fetch/incoming", post(auto_transfer::fetch_incoming))
         .route("/auto_transfer/fetch/outgoing", post(auto_transfer::fetch_outgoing))
         .route("/auto_transfer/delete", post(auto_transfer::delete))
+        .route("/auto_transfer/pause", post(auto_transfer::pause))
+        .route("/auto_transfer/resume", post(auto_transfer::resume))
+        .route("/auto_transfer/history", post(auto_transfer::history))
+        .route("/auto_transfer/preview", post(auto_transfer::preview))
+        .route("/auto_transfer/forecast", post(forecast::fetch))
+        .route("/round_up/set", post(round_up::set))
+        .route("/round_up/fetch", post(round_up::fetch))
+        .route("/round_up/delete", post(round_up::delete))
+        .route("/transfer_rule/create", post(transfer_rule::create))
+        .route("/transfer_rule/fetch", post(transfer_rule::fetch))
+        .route("/transfer_rule/delete", post(transfer_rule::delete))
 
         .route("/transfer/account/account", post(account::account_transfer))
         .route("/transfer/account/user", post(account::account_to_user_transfer))
         .route("/transfer/user/user", post(account::user_transfer))
 
         .route("/log/fetch", post(log::fetch))
+        .route("/log/export/ofx", post(export::ofx))
+        .route("/log/export/qif", post(export::qif))
+        .route("/log/import/csv", post(import::csv))
+        .route("/log/import/post", post(import::post_import))
+        .route("/log/analytics", post(analytics::fetch))
 
         .layer(
             CorsLayer::new()
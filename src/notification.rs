@@ -0,0 +1,126 @@
+// things the user should hear about without having asked, fetched by clients
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, Pool, Postgres};
+use strum_macros::{Display, EnumString};
+
+use crate::{extractor_error::ExtractorError, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: i64,
+    pub owner: String,
+    #[sqlx(try_from = "String")]
+    pub species: NotificationSpecies,
+    pub subject: i64, // id of whatever the notification is about, depends on species
+    pub message: String,
+    pub timestamp: f64,
+    pub seen: bool,
+}
+
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum NotificationSpecies {
+    LimitThreshold, // subject is the limit
+    AutoTransferPaused, // subject is the auto transfer
+    RoundUpStopped, // subject is the round up, which no longer exists
+}
+impl TryFrom<String> for NotificationSpecies {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Notification {
+    pub async fn push(db: &Pool<Postgres>, owner: &str, species: NotificationSpecies, subject: i64, message: String) {
+        sqlx::query("insert into plutus.notification(owner, species, subject, message, timestamp) values($1, $2, $3, $4, $5);")
+            .bind(owner)
+            .bind(species.to_string())
+            .bind(subject)
+            .bind(message)
+            .bind(utils::get_time() as f64)
+            .execute(db)
+            .await.unwrap();
+    }
+
+    // newest first, older than the notification `before` if given
+    pub async fn fetch_all(db: &Pool<Postgres>, owner: String, unseen_only: bool, amount: i32, before: Option<i64>) -> Vec<Notification> {
+        sqlx::query_as::<_, Notification>("
+            select * from plutus.notification
+            where owner = $1 and (not $2 or not seen) and ($3::bigint is null or id < $3)
+            order by id desc limit $4;
+        ")
+            .bind(owner)
+            .bind(unseen_only)
+            .bind(before)
+            .bind(amount as i64)
+            .fetch_all(db)
+            .await.unwrap()
+    }
+
+    pub async fn mark_seen(db: &Pool<Postgres>, owner: String, ids: Vec<i64>) {
+        sqlx::query("update plutus.notification set seen = true where owner = $1 and id = any($2);")
+            .bind(owner)
+            .bind(ids)
+            .execute(db)
+            .await.unwrap();
+    }
+}
+
+#[derive(Serialize)]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>,
+    pub cursor: Option<String>, // none when there is nothing left
+}
+impl NotificationPage {
+    pub async fn fetch(db: &Pool<Postgres>, owner: String, unseen_only: bool, amount: i32, before: Option<i64>) -> NotificationPage {
+        // one extra to know if theres another page
+        let mut notifications = Notification::fetch_all(db, owner, unseen_only, amount + 1, before).await;
+        let cursor = if notifications.len() > amount as usize {
+            notifications.truncate(amount as usize);
+            notifications.last().map(|n| format!("{:x}", n.id))
+        } else {
+            None
+        };
+
+        NotificationPage { notifications, cursor }
+    }
+}
+
+// newest first, marks everything returned as seen
+pub async fn fetch(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("amount", PlutusFormat::Number)
+    ], |db, session, query| async move {
+        // optional args
+        // "unseen" : true -> only notifications that havent been fetched before
+        // "cursor" : from the previous page
+        let unseen_only = match utils::optional_query::<bool>("unseen", &query) {
+            Ok(u) => u.unwrap_or(false),
+            Err(e) => return Outcome::Plutus(e)
+        };
+
+        // cursors are opaque to clients, just hand back whatever was given
+        let before = match query.get("cursor").map(|c| i64::from_str_radix(c, 16)) {
+            None => None,
+            Some(Ok(c)) => Some(c),
+            Some(Err(_)) => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        let amount = utils::from_query("amount", &query).parse::<i32>().unwrap().clamp(1, 100);
+
+        let result = NotificationPage::fetch(&db, session.user.clone(), unseen_only, amount, before).await;
+        Notification::mark_seen(&db, session.user, result.notifications.iter().map(|n| n.id).collect()).await;
+
+        Outcome::Data(serde_json::to_string(&result).unwrap())
+    }).await
+}
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::{account::AccountError, limit::LimitError, session::SessionError, auto_transfer::AutoTransferError, user::UserError};
+use crate::{account::AccountError, limit::LimitError, session::SessionError, auto_transfer::AutoTransferError, user::UserError, import::ImportError, round_up::RoundUpError, transfer_rule::TransferRuleError};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum PlutusError {
@@ -15,7 +16,7 @@ pub enum PlutusError {
     InvalidFormat, 
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, PartialEq)]
 pub enum Outcome
 {
     Success,
@@ -25,12 +26,32 @@ pub enum Outcome
     Session(SessionError),
     AutoTransfer(AutoTransferError),
     User(UserError),
+    Import(ImportError),
+    RoundUp(RoundUpError),
+    TransferRule(TransferRuleError),
 
     Plutus(PlutusError),
 
     Unspecified
 }
 
+impl Outcome {
+    // "Success" or "<category>::<variant>", eg: "Account::InsufficientBalance"
+    // stored alongside logs so they can be filtered on
+    pub fn code(&self) -> String {
+        match serde_json::to_value(self).unwrap() {
+            Value::Object(o) => match o.into_iter().next() {
+                Some((category, Value::String(variant))) => format!("{category}::{variant}"),
+                Some((category, Value::Object(variant))) => format!("{category}::{}", variant.keys().next().cloned().unwrap_or_default()),
+                Some((category, _)) => category,
+                None => String::new()
+            },
+            Value::String(s) => s,
+            _ => String::new()
+        }
+    }
+}
+
 pub enum PlutusFormat {
     Unspecified, // anything goes
 
@@ -0,0 +1,219 @@
+// rounding outgoing transfers up and putting the difference into savings
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, Pool, Postgres};
+
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{self, Source}, notification::{Notification, NotificationSpecies}, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct RoundUp {
+    pub id: i64,
+    pub account: i64, // one per account
+    pub savings: i64,
+    pub multiple: f64, // rounds up to the next multiple of this
+    pub batched: bool, // collected in pending and moved once per day instead of after every transfer
+    pub pending: f64,
+}
+impl RoundUp {
+    // tasks
+    pub async fn sweep_all(db: &Pool<Postgres>) {
+        // run once per day
+        let round_ups = sqlx::query_as::<_, RoundUp>("select * from plutus.round_up where pending > 0;")
+            .fetch_all(db)
+            .await.unwrap();
+
+        for r in round_ups {
+            match Account::transfer(db, r.account, r.savings, r.pending, Source::RoundUp).await {
+                None => {
+                    sqlx::query("update plutus.round_up set pending = pending - $1 where id = $2;")
+                        .bind(r.pending)
+                        .bind(r.id)
+                        .execute(db)
+                        .await.unwrap();
+                },
+                Some(Outcome::Account(AccountError::NoExist)) => r.stop(db).await,
+                Some(_) => {} // stays pending until it goes through
+            }
+        }
+    }
+    //
+
+    // what `amount` is short of the next multiple, to the cent
+    pub fn difference(&self, amount: f64) -> f64 {
+        let rounded = (amount / self.multiple).ceil() * self.multiple;
+        ((rounded - amount) * 100f64).round() / 100f64
+    }
+
+    // after a successful transfer of `amount` out of `account`
+    pub async fn on_outgoing(db: &Pool<Postgres>, account: i64, destination: i64, amount: f64) {
+        let r = match RoundUp::fetch(db, account).await {
+            Some(r) if r.savings != destination => r, // putting money into savings isnt spending it
+            _ => return
+        };
+
+        let difference = r.difference(amount);
+        if difference <= 0f64 {
+            return;
+        }
+
+        if !r.batched {
+            match Box::pin(Account::transfer(db, r.account, r.savings, difference, Source::RoundUp)).await {
+                None => return,
+                Some(Outcome::Account(AccountError::NoExist)) => return r.stop(db).await,
+                Some(_) => {} // moving it right away can fail as well, it waits for the daily sweep then
+            }
+        }
+
+        sqlx::query("update plutus.round_up set pending = pending + $1 where id = $2;")
+            .bind(difference)
+            .bind(r.id)
+            .execute(db)
+            .await.unwrap();
+    }
+
+    // one of the accounts is gone so it could never go through, the owner is told
+    async fn stop(&self, db: &Pool<Postgres>) {
+        sqlx::query("delete from plutus.round_up where id = $1;")
+            .bind(self.id)
+            .execute(db)
+            .await.unwrap();
+
+        let owner = match Account::fetch(db, self.account).await {
+            Some(a) => Some(a),
+            None => Account::fetch(db, self.savings).await
+        };
+        if let Some(a) = owner {
+            Notification::push(db, &a.owner, NotificationSpecies::RoundUpStopped, self.id, format!("round up on account {} was removed, {} was left unsaved", self.account, self.pending)).await;
+        }
+    }
+
+    pub async fn fetch(db: &Pool<Postgres>, account: i64) -> Option<RoundUp> {
+        sqlx::query_as::<_, RoundUp>("select * from plutus.round_up where account = $1;")
+            .bind(account)
+            .fetch_optional(db)
+            .await.unwrap()
+    }
+
+    // replaces whatever rule the account had, anything pending is kept
+    pub async fn set(db: &Pool<Postgres>, account: i64, savings: i64, multiple: f64, batched: bool) -> RoundUp {
+        sqlx::query_as::<_, RoundUp>("
+            insert into plutus.round_up(account, savings, multiple, batched) values($1, $2, $3, $4)
+            on conflict (account) do update set savings = $2, multiple = $3, batched = $4
+            returning *;
+        ")
+            .bind(account)
+            .bind(savings)
+            .bind(multiple)
+            .bind(batched)
+            .fetch_one(db)
+            .await.unwrap()
+    }
+
+    // anything pending is moved to savings first
+    pub async fn delete(db: &Pool<Postgres>, account: i64) -> Option<RoundUpError> {
+        let r = match RoundUp::fetch(db, account).await {
+            Some(r) => r,
+            None => return Some(RoundUpError::RoundUpDoesntExist)
+        };
+
+        if r.pending > 0f64 {
+            match Account::transfer(db, r.account, r.savings, r.pending, Source::RoundUp).await {
+                None | Some(Outcome::Account(AccountError::NoExist)) => {}, // nowhere to move it to
+                Some(_) => return Some(RoundUpError::PendingNotMoved)
+            }
+        }
+
+        sqlx::query("delete from plutus.round_up where account = $1;")
+            .bind(account)
+            .execute(db)
+            .await.unwrap();
+
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub enum RoundUpError {
+    RoundUpDoesntExist,
+
+    TargetSame, // savings is the account itself
+    PendingNotMoved, // when deleting, try again once the pending amount can go through
+}
+
+pub async fn set(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("savings", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        // optional args
+        // "multiple" : rounds up to the next multiple of this, defaults to 1
+        // "batched" : true -> moved once per day instead of after every transfer
+        let id = match log::owned_account(&db, &query, session.user.clone()).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        let savings = utils::from_query("savings", &query).parse::<i64>().unwrap();
+        if !Account::is_owner(&db, savings, session.user).await {
+            return Outcome::Account(AccountError::NoExist);
+        }
+        if savings == id {
+            return Outcome::RoundUp(RoundUpError::TargetSame);
+        }
+
+        let (multiple, batched) = match (utils::optional_query::<f64>("multiple", &query), utils::optional_query::<bool>("batched", &query)) {
+            (Ok(m), Ok(b)) if m.is_none_or(|m| m.is_finite() && m > 0f64) => (m.unwrap_or(1f64), b.unwrap_or(false)),
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        Outcome::Data(serde_json::to_string(&RoundUp::set(&db, id, savings, multiple, batched).await).unwrap())
+    }).await
+}
+
+pub async fn fetch(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        match RoundUp::fetch(&db, id).await {
+            Some(r) => Outcome::Data(serde_json::to_string(&r).unwrap()),
+            None => Outcome::RoundUp(RoundUpError::RoundUpDoesntExist)
+        }
+    }).await
+}
+
+pub async fn delete(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        match RoundUp::delete(&db, id).await {
+            Some(e) => Outcome::RoundUp(e),
+            None => Outcome::Success
+        }
+    }).await
+}
@@ -0,0 +1,242 @@
+// when auto transfers run, at the resolution of days since the tasks only run once per day
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{plutus_error::PlutusError, utils};
+
+// how far ahead to look for the next matching day of a cron expression
+const CRON_HORIZON: i64 = 366 * 8;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Schedule {
+    Interval(i32), // every x number of days
+    DayOfMonth(u32), // clamped to the last day in shorter months
+    LastBusinessDay, // last monday-friday of the month
+    Weekly { weekday: u32, weeks: u32 }, // weekday 0 is monday, runs every `weeks` weeks
+    Cron(String), // "day-of-month month day-of-week", eg. "1,15 * *" or "* * 4"
+}
+
+// 0 is monday, epoch day 0 was a thursday
+pub fn weekday(day: i64) -> u32 {
+    (day + 3).rem_euclid(7) as u32
+}
+
+fn last_business_day(year: i64, month: u32) -> i64 {
+    let last = utils::epoch_day_from_civil(year, month, utils::days_in_month(year, month));
+    match weekday(last) {
+        5 => last - 1,
+        6 => last - 2,
+        _ => last
+    }
+}
+
+fn next_month(year: i64, month: u32) -> (i64, u32) {
+    if month == 12 { (year + 1, 1) } else { (year, month + 1) }
+}
+
+// a single cron field, "*", "5", "1-5", "*/2", "1,15" or any mix of those separated by commas
+fn cron_field(raw: &str, min: u32, max: u32) -> Option<Vec<u32>> {
+    let mut result = vec![];
+    for part in raw.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (a.parse::<u32>().ok()?, b.parse::<u32>().ok()?),
+                None => {
+                    let v = range.parse::<u32>().ok()?;
+                    (v, v)
+                }
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        result.extend((start..=end).step_by(step as usize));
+    }
+    Some(result)
+}
+
+struct Cron {
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    // like cron, when both days and weekdays are restricted either one matching is enough
+    either: bool,
+}
+impl Cron {
+    fn parse(raw: &str) -> Option<Cron> {
+        let fields = raw.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 3 {
+            return None;
+        }
+
+        Some(Cron {
+            days: cron_field(fields[0], 1, 31)?,
+            months: cron_field(fields[1], 1, 12)?,
+            weekdays: cron_field(fields[2], 0, 6)?,
+            either: fields[0] != "*" && fields[2] != "*"
+        })
+    }
+
+    fn matches(&self, day: i64) -> bool {
+        let (_, m, d) = utils::civil_from_epoch_day(day);
+        if !self.months.contains(&m) {
+            return false;
+        }
+
+        let (by_day, by_weekday) = (self.days.contains(&d), self.weekdays.contains(&weekday(day)));
+        if self.either { by_day || by_weekday } else { by_day && by_weekday }
+    }
+}
+
+impl Schedule {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Schedule::Interval(d) => *d > 0,
+            Schedule::DayOfMonth(d) => (1..=31).contains(d),
+            Schedule::LastBusinessDay => true,
+            Schedule::Weekly { weekday, weeks } => *weekday < 7 && *weeks > 0,
+            Schedule::Cron(c) => Cron::parse(c).is_some() && self.next(utils::get_epoch_day()).is_some()
+        }
+    }
+
+    // first day (epoch day) to run on after `last`, none if it never runs again
+    pub fn next(&self, last: i64) -> Option<i64> {
+        match self {
+            Schedule::Interval(d) => Some(last + *d as i64),
+            Schedule::DayOfMonth(d) => {
+                let (mut y, mut m, _) = utils::civil_from_epoch_day(last);
+                loop {
+                    let candidate = utils::epoch_day_from_civil(y, m, (*d).min(utils::days_in_month(y, m)));
+                    if candidate > last {
+                        return Some(candidate);
+                    }
+                    (y, m) = next_month(y, m);
+                }
+            },
+            Schedule::LastBusinessDay => {
+                let (y, m, _) = utils::civil_from_epoch_day(last);
+                let candidate = last_business_day(y, m);
+                if candidate > last {
+                    Some(candidate)
+                } else {
+                    let (y, m) = next_month(y, m);
+                    Some(last_business_day(y, m))
+                }
+            },
+            Schedule::Weekly { weekday: w, weeks } => {
+                if weekday(last) == *w {
+                    Some(last + 7 * *weeks as i64)
+                } else {
+                    Some(last + ((*w + 7 - weekday(last)) % 7) as i64)
+                }
+            },
+            Schedule::Cron(c) => {
+                let cron = Cron::parse(c)?;
+                ((last + 1)..=(last + CRON_HORIZON)).find(|d| cron.matches(*d))
+            }
+        }
+    }
+
+    // first day (epoch day) to run on, starting from `day`
+    pub fn first_from(&self, day: i64) -> Option<i64> {
+        match self {
+            Schedule::Interval(_) => Some(day),
+            Schedule::Weekly { weekday: w, .. } => Some(day + ((*w + 7 - weekday(day)) % 7) as i64),
+            _ => self.next(day - 1)
+        }
+    }
+
+    // optional args
+    // "duration" : every x number of days
+    // "schedule" : DayOfMonth ("day"), LastBusinessDay, Weekly ("weekday", "weeks" defaults to 1), Cron ("cron")
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Schedule, PlutusError> {
+        let schedule = match (utils::optional_query::<i32>("duration", query)?, query.get("schedule").map(|s| s.as_str())) {
+            (Some(d), None) => Schedule::Interval(d),
+            (None, Some("DayOfMonth")) => Schedule::DayOfMonth(utils::optional_query::<u32>("day", query)?.ok_or(PlutusError::InvalidArguments)?),
+            (None, Some("LastBusinessDay")) => Schedule::LastBusinessDay,
+            (None, Some("Weekly")) => Schedule::Weekly {
+                weekday: utils::optional_query::<u32>("weekday", query)?.ok_or(PlutusError::InvalidArguments)?,
+                weeks: utils::optional_query::<u32>("weeks", query)?.unwrap_or(1)
+            },
+            (None, Some("Cron")) => Schedule::Cron(utils::optional_query::<String>("cron", query)?.ok_or(PlutusError::InvalidArguments)?),
+            (None, None) => return Err(PlutusError::InvalidArguments),
+            _ => return Err(PlutusError::InvalidFormat)
+        };
+
+        if schedule.is_valid() { Ok(schedule) } else { Err(PlutusError::InvalidFormat) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(year: i64, month: u32, day: u32) -> i64 {
+        utils::epoch_day_from_civil(year, month, day)
+    }
+
+    #[test]
+    fn day_of_month_clamps_to_february() {
+        let schedule = Schedule::DayOfMonth(31);
+        assert_eq!(schedule.next(day(2024, 1, 31)), Some(day(2024, 2, 29)));
+        assert_eq!(schedule.next(day(2023, 1, 31)), Some(day(2023, 2, 28)));
+        // back to the 31st once the month has one
+        assert_eq!(schedule.next(day(2024, 2, 29)), Some(day(2024, 3, 31)));
+    }
+
+    #[test]
+    fn weekly_every_other_week() {
+        let schedule = Schedule::Weekly { weekday: 0, weeks: 2 };
+        // wednesday -> the monday after
+        let first = schedule.first_from(day(2025, 1, 15)).unwrap();
+        assert_eq!(first, day(2025, 1, 20));
+        assert_eq!(schedule.next(first), Some(day(2025, 2, 3)));
+        assert_eq!(schedule.next(day(2025, 2, 3)), Some(day(2025, 2, 17)));
+    }
+
+    #[test]
+    fn last_business_day_skips_the_weekend() {
+        let schedule = Schedule::LastBusinessDay;
+        // may 2025 ends on a saturday, august 2025 on a sunday
+        assert_eq!(schedule.next(day(2025, 5, 1)), Some(day(2025, 5, 30)));
+        assert_eq!(schedule.next(day(2025, 8, 1)), Some(day(2025, 8, 29)));
+        // already past it this month
+        assert_eq!(schedule.next(day(2025, 5, 30)), Some(day(2025, 6, 30)));
+    }
+
+    #[test]
+    fn cron_day_or_weekday() {
+        // both restricted, either one is enough: the 13th or any friday (0 being monday)
+        let schedule = Schedule::Cron("13 * 4".to_string());
+        assert_eq!(schedule.next(day(2025, 1, 1)), Some(day(2025, 1, 3)));
+        assert_eq!(schedule.next(day(2025, 1, 10)), Some(day(2025, 1, 13)));
+        assert_eq!(schedule.next(day(2025, 1, 13)), Some(day(2025, 1, 17)));
+    }
+
+    #[test]
+    fn cron_month_and_weekday() {
+        // only one of day/weekday restricted, everything has to match: mondays in february
+        let schedule = Schedule::Cron("* 2 0".to_string());
+        assert_eq!(schedule.next(day(2025, 1, 1)), Some(day(2025, 2, 3)));
+        assert_eq!(schedule.next(day(2025, 2, 24)), Some(day(2026, 2, 2)));
+        // the 13th, whatever weekday it is
+        assert_eq!(Schedule::Cron("13 * *".to_string()).next(day(2025, 1, 1)), Some(day(2025, 1, 13)));
+    }
+
+    #[test]
+    fn invalid_cron() {
+        assert!(!Schedule::Cron("32 * *".to_string()).is_valid());
+        assert!(!Schedule::Cron("* * 7".to_string()).is_valid());
+        assert!(!Schedule::Cron("*/0 * *".to_string()).is_valid());
+        assert!(!Schedule::Cron("* *".to_string()).is_valid());
+        // never happens
+        assert!(!Schedule::Cron("31 2 *".to_string()).is_valid());
+    }
+}
@@ -0,0 +1,126 @@
+// end of day balances, and looking up balances at any point in the past
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, Pool, Postgres};
+
+use crate::{analytics::{Period, MAX_RANGE}, extractor_error::ExtractorError, log::{self, Log}, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub account: i64,
+    pub day: i64,
+    pub timestamp: f64,
+    pub balance: f64,
+    pub last_log: Option<i64>, // latest log included in balance
+}
+impl Snapshot {
+    // tasks
+    pub async fn record_all(db: &Pool<Postgres>) {
+        // run once per day, before anything else moves money
+        sqlx::query("
+            insert into plutus.balance_snapshot(account, day, timestamp, balance, last_log)
+            select id, $1, $2, balance, (select coalesce(max(id), 0) from plutus.log) from plutus.account
+            on conflict (account, day) do nothing;
+        ")
+            .bind(utils::get_epoch_day())
+            .bind(utils::get_time() as f64)
+            .execute(db)
+            .await.unwrap();
+    }
+    //
+
+    // latest snapshot taken at or before `timestamp`
+    pub async fn fetch_before(db: &Pool<Postgres>, account: i64, timestamp: i64) -> Option<Snapshot> {
+        sqlx::query_as::<_, Snapshot>("select * from plutus.balance_snapshot where account = $1 and timestamp <= $2 order by timestamp desc limit 1;")
+            .bind(account)
+            .bind(timestamp as f64)
+            .fetch_optional(db)
+            .await.unwrap()
+    }
+}
+
+// snapshot, then replay whatever happened between it and `timestamp`
+pub async fn balance_at(db: &Pool<Postgres>, account: i64, timestamp: i64) -> f64 {
+    let (base, logs) = match Snapshot::fetch_before(db, account, timestamp).await {
+        Some(Snapshot { balance, last_log: Some(l), .. }) => (balance, Log::fetch_after(db, account, l, timestamp).await),
+        Some(s) => (s.balance, Log::fetch_range(db, account, s.timestamp as i64 + 1, timestamp).await),
+        None => (0f64, Log::fetch_range(db, account, i64::MIN, timestamp).await) // accounts start out empty
+    };
+
+    base + logs.iter().map(|l| l.signed_amount(account)).sum::<f64>()
+}
+
+// balance at the end of every period between `from` and `to`, as (epoch seconds, balance)
+pub async fn balance_series(db: &Pool<Postgres>, account: i64, from: i64, to: i64, period: Period) -> Vec<(i64, f64)> {
+    let mut balance = balance_at(db, account, from).await;
+    let logs = Log::fetch_range(db, account, from.saturating_add(1), to).await;
+    let mut logs = logs.iter().peekable();
+
+    let mut result = vec![];
+    let mut start = period.start_of(from.div_euclid(86400));
+    while start * 86400 <= to {
+        let end = (period.next(start) * 86400 - 1).min(to);
+        while let Some(l) = logs.next_if(|l| l.timestamp as i64 <= end) {
+            balance += l.signed_amount(account);
+        }
+
+        result.push((end, balance));
+        start = period.next(start);
+    }
+    result
+}
+
+pub async fn at(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("timestamp", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        Outcome::Data(serde_json::to_string(
+            &balance_at(&db, id, utils::from_query("timestamp", &query).parse::<i64>().unwrap()).await
+        ).unwrap())
+    }).await
+}
+
+pub async fn series(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("from", PlutusFormat::BigNumber),
+        ("to", PlutusFormat::BigNumber),
+        ("period", PlutusFormat::Unspecified)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        let period = match utils::from_query("period", &query).parse::<Period>() {
+            Ok(p) => p,
+            Err(_) => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        let from = utils::from_query("from", &query).parse::<i64>().unwrap();
+        let to = utils::from_query("to", &query).parse::<i64>().unwrap();
+        if to < from || to.saturating_sub(from) > MAX_RANGE {
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+
+        Outcome::Data(serde_json::to_string(&balance_series(&db, id, from, to, period).await).unwrap())
+    }).await
+}
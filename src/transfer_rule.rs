@@ -0,0 +1,218 @@
+// rules that pass on part of what comes into an account, eg. splitting a salary
+
+use std::collections::HashMap;
+
+use axum::{extract::{Query, State}, response::IntoResponse, Json};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, Pool, Postgres};
+use strum_macros::{Display, EnumString};
+
+use crate::{account::{Account, AccountError}, extractor_error::ExtractorError, log::{self, Log, Source}, plutus_error::{Outcome, PlutusError, PlutusFormat}, session::RawSessionID, utils, AppState};
+
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct TransferRule {
+    pub id: i64,
+    pub account: i64, // incoming transfers to this trigger the rule
+    pub destination: i64,
+    pub amount: f64, // meaning depends on amount_species
+    #[sqlx(try_from = "String")]
+    pub amount_species: RuleAmountSpecies,
+
+    // only incoming transfers matching these trigger the rule
+    pub sender: Option<i64>,
+    pub sender_user: Option<String>,
+    pub min_amount: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, EnumString, Display, Clone, Copy, PartialEq)]
+pub enum RuleAmountSpecies {
+    Fixed, // amount as is
+    Percentage, // amount percent of the incoming transfer
+}
+impl TryFrom<String> for RuleAmountSpecies {
+    type Error = strum::ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TransferRule {
+    // only transfers made by users and auto transfers set rules off
+    // so transfers made by rules (or round ups) can never set off more of them
+    pub fn triggered_by(source: &Source) -> bool {
+        matches!(source, Source::User(_) | Source::AutoTransfer(_))
+    }
+
+    pub fn matches(&self, origin: &Account, amount: f64) -> bool {
+        self.sender.is_none_or(|s| s == origin.id) &&
+            self.sender_user.as_ref().is_none_or(|u| *u == origin.owner) &&
+            self.min_amount.is_none_or(|m| amount >= m)
+    }
+
+    // rounded down to the cent
+    pub fn amount_for(&self, incoming: f64) -> f64 {
+        match self.amount_species {
+            RuleAmountSpecies::Fixed => self.amount,
+            RuleAmountSpecies::Percentage => (incoming * self.amount).floor() / 100f64
+        }
+    }
+
+    // after `amount` successfully came into `destination` from `origin`, in the order the rules were made
+    pub async fn on_incoming(db: &Pool<Postgres>, origin: &Account, destination: &Account, amount: f64) {
+        for r in TransferRule::fetch_all(db, destination.id).await.into_iter().filter(|r| r.matches(origin, amount)) {
+            let outgoing = r.amount_for(amount);
+            if outgoing <= 0f64 {
+                continue;
+            }
+
+            if let Some(e) = Box::pin(Account::transfer(db, r.account, r.destination, outgoing, Source::Rule)).await {
+                Log::append_at(db, outgoing, Source::Rule(r.account), Source::Rule(r.destination), e, utils::get_time()).await;
+            }
+        }
+    }
+
+    pub async fn create(db: &Pool<Postgres>, candidate: TransferRule) -> TransferRule {
+        sqlx::query_as::<_, TransferRule>("
+            insert into plutus.transfer_rule(account, destination, amount, amount_species, sender, sender_user, min_amount)
+            values($1, $2, $3, $4, $5, $6, $7) returning *;
+        ")
+            .bind(candidate.account)
+            .bind(candidate.destination)
+            .bind(candidate.amount)
+            .bind(candidate.amount_species.to_string())
+            .bind(candidate.sender)
+            .bind(candidate.sender_user)
+            .bind(candidate.min_amount)
+            .fetch_one(db)
+            .await.unwrap()
+    }
+
+    pub async fn fetch(db: &Pool<Postgres>, id: i64) -> Option<TransferRule> {
+        sqlx::query_as::<_, TransferRule>("select * from plutus.transfer_rule where id = $1;")
+            .bind(id)
+            .fetch_optional(db)
+            .await.unwrap()
+    }
+
+    pub async fn fetch_all(db: &Pool<Postgres>, account: i64) -> Vec<TransferRule> {
+        sqlx::query_as::<_, TransferRule>("select * from plutus.transfer_rule where account = $1 order by id;")
+            .bind(account)
+            .fetch_all(db)
+            .await.unwrap()
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, id: i64) {
+        sqlx::query("delete from plutus.transfer_rule where id = $1;")
+            .bind(id)
+            .execute(db)
+            .await.unwrap();
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub enum TransferRuleError {
+    RuleDoesntExist,
+
+    ToDoesntExist,
+    TargetSame, // when the destination is the account itself
+}
+
+pub async fn create(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber),
+        ("destination", PlutusFormat::BigNumber),
+        ("amount", PlutusFormat::Float)
+    ], |db, session, query| async move {
+        // optional args
+        // "amount_species" : Fixed (default), Percentage of the incoming transfer
+        // "sender" : only transfers from this account
+        // "sender_user" : only transfers from this user's accounts
+        // "min_amount" : only transfers of at least this much
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        let destination = utils::from_query("destination", &query).parse::<i64>().unwrap();
+        if Account::fetch(&db, destination).await.is_none() {
+            return Outcome::TransferRule(TransferRuleError::ToDoesntExist);
+        }
+        if destination == id {
+            return Outcome::TransferRule(TransferRuleError::TargetSame);
+        }
+
+        let candidate = match (
+            utils::optional_query::<RuleAmountSpecies>("amount_species", &query),
+            utils::optional_query::<i64>("sender", &query),
+            utils::optional_query::<String>("sender_user", &query),
+            utils::optional_query::<f64>("min_amount", &query)
+        ) {
+            (Ok(amount_species), Ok(sender), Ok(sender_user), Ok(min_amount)) => TransferRule {
+                id: 0,
+                account: id,
+                destination,
+                amount: utils::from_query("amount", &query).parse::<f64>().unwrap(),
+                amount_species: amount_species.unwrap_or(RuleAmountSpecies::Fixed),
+                sender,
+                sender_user,
+                min_amount
+            },
+            _ => return Outcome::Plutus(PlutusError::InvalidFormat)
+        };
+
+        let valid = match candidate.amount_species {
+            RuleAmountSpecies::Fixed => candidate.amount > 0f64,
+            RuleAmountSpecies::Percentage => candidate.amount > 0f64 && candidate.amount <= 100f64
+        };
+        if !valid {
+            return Outcome::Plutus(PlutusError::InvalidFormat);
+        }
+
+        Outcome::Data(serde_json::to_string(&TransferRule::create(&db, candidate).await).unwrap())
+    }).await
+}
+
+pub async fn fetch(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("account", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = match log::owned_account(&db, &query, session.user).await {
+            Ok(i) => i,
+            Err(e) => return e
+        };
+
+        Outcome::Data(serde_json::to_string(&TransferRule::fetch_all(&db, id).await).unwrap())
+    }).await
+}
+
+pub async fn delete(
+    State(app_state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    WithRejection(Json(session_id), _): WithRejection<Json<RawSessionID>, ExtractorError>
+) -> impl IntoResponse {
+    utils::request_boiler(app_state, query, session_id, vec![
+        ("rule", PlutusFormat::BigNumber)
+    ], |db, session, query| async move {
+        let id = utils::from_query("rule", &query).parse::<i64>().unwrap();
+
+        match TransferRule::fetch(&db, id).await {
+            Some(r) if Account::is_owner(&db, r.account, session.user).await => {},
+            Some(_) => return Outcome::Account(AccountError::NoPermission),
+            None => return Outcome::TransferRule(TransferRuleError::RuleDoesntExist)
+        }
+
+        TransferRule::delete(&db, id).await;
+
+        Outcome::Success
+    }).await
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, future::Future, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::HashMap, future::Future, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
 
-use axum::response::IntoResponse;
+use axum::{http::header, response::{IntoResponse, Response}};
 use sqlx::{Pool, Postgres};
 
 use crate::{plutus_error::{self, PlutusError, PlutusFormat, Outcome}, session::{RawSessionID, Session}, AppState};
@@ -21,6 +21,17 @@ pub fn from_query(k: &str, q: &HashMap<String, String>) -> String {
     return urlencoding::decode(q.get(&k.to_string()).unwrap().clone().as_str()).unwrap().to_string()
 }
 
+// for args that can be left out, but have to be valid when given
+pub fn optional_query<T: FromStr>(k: &str, q: &HashMap<String, String>) -> Result<Option<T>, PlutusError> {
+    match q.get(k) {
+        Some(v) => match urlencoding::decode(v).map(|v| v.parse::<T>()) {
+            Ok(Ok(v)) => Ok(Some(v)),
+            _ => Err(PlutusError::InvalidFormat)
+        },
+        None => Ok(None)
+    }
+}
+
 pub async fn request_boiler<F, Fut>(
     app_state: AppState,
     query: HashMap<String, String>,
@@ -68,6 +79,94 @@ where
     }
 }
 
+// a file sent back as an attachment instead of the usual json Outcome
+pub struct Download {
+    pub filename: String,
+    pub content_type: &'static str,
+    pub body: String
+}
+impl IntoResponse for Download {
+    fn into_response(self) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, self.content_type.to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", self.filename))
+            ],
+            self.body
+        ).into_response()
+    }
+}
+
+// same as request_boiler, but a successful result is downloaded as a file
+// errors are still returned as json Outcomes
+pub async fn request_boiler_download<F, Fut>(
+    app_state: AppState,
+    query: HashMap<String, String>,
+    session_id: RawSessionID,
+
+    plutus_check: Vec<(&str, PlutusFormat)>,
+
+    func: F) -> Response
+where
+    F: Fn(Pool<Postgres>, Session, HashMap<String, String>) -> Fut,
+    Fut: Future<Output = Result<Download, Outcome>>,
+{
+    match session_id.into_session(&app_state.db).await {
+        Ok(s) => {
+            match plutus_error::check(&query, plutus_check) {
+                PlutusError::Success => match func(app_state.db, s, query).await {
+                    Ok(d) => d.into_response(),
+                    Err(o) => serde_json::to_string(&o).unwrap().into_response()
+                },
+                r => serde_json::to_string(&Outcome::Plutus(r)).unwrap().into_response()
+            }
+        },
+        Err(e) => serde_json::to_string(&Outcome::Session(e)).unwrap().into_response()
+    }
+}
+
+// #region date
+// civil (proleptic gregorian) calendar <-> epoch days, all in UTC
+// https://howardhinnant.github.io/date_algorithms.html
+pub fn epoch_day_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+pub fn civil_from_epoch_day(epoch_day: i64) -> (i64, u32, u32) {
+    // (year, month, day)
+    let z = epoch_day + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 => if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+// "YYYYMMDDHHMMSS"
+pub fn format_timestamp_compact(timestamp: i64) -> String {
+    let (y, m, d) = civil_from_epoch_day(timestamp.div_euclid(86400));
+    let s = timestamp.rem_euclid(86400);
+    format!("{y:04}{m:02}{d:02}{:02}{:02}{:02}", s / 3600, (s % 3600) / 60, s % 60)
+}
+// #endregion
+
 // #region random
 pub fn async_rng_range(start: f64, end: f64) -> f64 {
     start + (rand::random::<f64>() * (end - start))
@@ -102,3 +201,26 @@ pub fn async_rng_item<T>(inv: &Vec<T>) -> &T {
     &inv[async_rng_index(inv)]
 }
 // #endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_round_trip() {
+        assert_eq!(epoch_day_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_epoch_day(-1), (1969, 12, 31));
+        for day in [-719468, -1, 0, 11016, 19782, 20000, 2932896] {
+            let (y, m, d) = civil_from_epoch_day(day);
+            assert_eq!(epoch_day_from_civil(y, m, d), day);
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2023, 2), 28);
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+    }
+}